@@ -1,5 +1,7 @@
 use super::*;
 
+pub mod cli;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PlayerSnapshot {
     pub skin: usize,
@@ -8,15 +10,19 @@ pub struct PlayerSnapshot {
     pub rot: f32,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct TimedData {
     pub time: f32,
     pub data: PlayerSnapshot,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct MoveData {
     pub data: Vec<TimedData>,
+    /// Skin and color of the recorded driver, unknown in old recordings
+    pub driver: Option<(usize, f32)>,
+    /// Unix time of the recording, unknown in old recordings
+    pub date: Option<u64>,
 }
 
 pub struct Result {
@@ -26,13 +32,18 @@ pub struct Result {
 
 impl MoveData {
     pub fn new() -> Self {
-        Self { data: vec![] }
+        Self {
+            data: vec![],
+            driver: None,
+            date: None,
+        }
     }
     pub fn push(&mut self, time: f32, data: Player) {
+        self.driver = Some((data.skin, data.color));
         self.data.push(TimedData {
             time,
             data: PlayerSnapshot {
-                skin: 0,
+                skin: data.skin,
                 pos: data.pos,
                 vel: data.vel,
                 rot: data.rot,
@@ -50,16 +61,17 @@ impl MoveData {
         let p1 = &self.data[index];
         let p2 = &self.data[(index + 1).min(self.data.len() - 1)];
         let t = (time - p1.time) / (p2.time - p1.time).max(1.0);
+        let (skin, color) = self.driver.unwrap_or((0, 0.0));
         Player {
-            skin: 0,
-            color: 0.0,
+            skin,
+            color,
             pos: p1.data.pos * (1.0 - t) + p2.data.pos * t,
             vel: p1.data.vel * (1.0 - t) + p2.data.vel * t,
             rot: p1.data.rot * (1.0 - t) + p2.data.rot * t,
         }
     }
 
-    fn result(&self) -> Result {
+    pub fn result(&self) -> Result {
         let data = self.data.last().unwrap();
         Result {
             time: data.time,
@@ -68,9 +80,197 @@ impl MoveData {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+/// How well AI bots drive, every value is from 0 to 1
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BotSkill {
+    /// Fraction of the max speed
+    pub speed: f32,
+    /// How much they slow down before turns
+    pub cornering: f32,
+    /// Random steering error
+    pub noise: f32,
+}
+
+/// Which recordings bots replay, based on their finish times
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BotDifficulty {
+    /// Slowest third
+    Easy,
+    Normal,
+    /// Fastest third
+    Hard,
+    /// Around this finish time percentile, 0 is the fastest
+    Percentile(f32),
+}
+
+impl BotDifficulty {
+    fn percentile(self) -> f32 {
+        match self {
+            Self::Easy => 100.0 * 5.0 / 6.0,
+            Self::Normal => 50.0,
+            Self::Hard => 100.0 / 6.0,
+            Self::Percentile(percentile) => percentile.clamp(0.0, 100.0),
+        }
+    }
+}
+
+impl std::str::FromStr for BotDifficulty {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        Ok(match s {
+            "easy" => Self::Easy,
+            "normal" => Self::Normal,
+            "hard" => Self::Hard,
+            _ => match s.parse() {
+                Ok(percentile) if (0.0..=100.0).contains(&percentile) => {
+                    Self::Percentile(percentile)
+                }
+                _ => return Err(format!("{s:?} is not easy, normal, hard or 0-100")),
+            },
+        })
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Data(pub HashMap<Track, Vec<MoveData>>);
 
+/// Start of every bots.data file, followed by the format version
+const MAGIC: &[u8; 8] = b"COOTSBOT";
+/// Bump when changing the layout and keep reading the old one in [Data::from_bytes]
+const FORMAT_VERSION: u32 = 3;
+
+/// Layouts of bots.data before it had a header, they are versions 0 to 2
+mod legacy {
+    /// Just the movement
+    pub mod v0 {
+        use super::super::*;
+
+        #[derive(Deserialize)]
+        pub struct MoveData {
+            pub data: Vec<TimedData>,
+        }
+
+        impl From<MoveData> for super::super::MoveData {
+            fn from(replay: MoveData) -> Self {
+                Self {
+                    data: replay.data,
+                    driver: None,
+                    date: None,
+                }
+            }
+        }
+
+        #[derive(Deserialize)]
+        pub struct Data(pub HashMap<Track, Vec<MoveData>>);
+    }
+
+    /// Driver appearance but no date
+    pub mod v1 {
+        use super::super::*;
+
+        #[derive(Deserialize)]
+        pub struct MoveData {
+            pub data: Vec<TimedData>,
+            pub driver: Option<(usize, f32)>,
+        }
+
+        impl From<MoveData> for super::super::MoveData {
+            fn from(replay: MoveData) -> Self {
+                Self {
+                    data: replay.data,
+                    driver: replay.driver,
+                    date: None,
+                }
+            }
+        }
+
+        #[derive(Deserialize)]
+        pub struct Data(pub HashMap<Track, Vec<MoveData>>);
+    }
+
+    /// Driver appearance and date
+    pub mod v2 {
+        use super::super::*;
+
+        #[derive(Deserialize)]
+        pub struct MoveData {
+            pub data: Vec<TimedData>,
+            pub driver: Option<(usize, f32)>,
+            pub date: Option<u64>,
+        }
+
+        impl From<MoveData> for super::super::MoveData {
+            fn from(replay: MoveData) -> Self {
+                Self {
+                    data: replay.data,
+                    driver: replay.driver,
+                    date: replay.date,
+                }
+            }
+        }
+
+        #[derive(Deserialize)]
+        pub struct Data(pub HashMap<Track, Vec<MoveData>>);
+    }
+}
+
+pub fn backup_path(path: impl AsRef<std::path::Path>) -> std::path::PathBuf {
+    let mut backup = path.as_ref().as_os_str().to_owned();
+    backup.push(".bak");
+    backup.into()
+}
+
+/// Writes to a temporary file first and renames it over the old one,
+/// so a crash never leaves a half written or missing file.
+/// The previous version is kept as a backup
+pub fn write_atomic(path: impl AsRef<std::path::Path>, bytes: &[u8]) -> anyhow::Result<()> {
+    use std::io::Write;
+    let path = path.as_ref();
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let mut file = std::fs::File::create(&tmp)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    if path.exists() {
+        let backup = backup_path(path);
+        let _ = std::fs::remove_file(&backup);
+        if std::fs::hard_link(path, &backup).is_err() {
+            std::fs::copy(path, &backup)?;
+        }
+    }
+    std::fs::rename(&tmp, path)?;
+    // Makes the rename itself survive a crash
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        let dir = if dir.as_os_str().is_empty() {
+            std::path::Path::new(".")
+        } else {
+            dir
+        };
+        std::fs::File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Renames a file that could not be read so the next save does not overwrite it
+pub fn move_aside(path: impl AsRef<std::path::Path>) {
+    let path = path.as_ref();
+    let mut aside = path.as_os_str().to_owned();
+    aside.push(format!(".unreadable-{}", now()));
+    match std::fs::rename(path, &aside) {
+        Ok(()) => warn!("Moved unreadable {path:?} to {aside:?}"),
+        Err(e) => error!("Failed to move unreadable {path:?} aside: {e}"),
+    }
+}
+
+/// Current unix time in seconds
+pub fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
 fn fix(player: &mut PlayerSnapshot) {
     if !player.pos.x.is_finite() {
         player.pos.x = 0.0;
@@ -90,13 +290,80 @@ fn fix(player: &mut PlayerSnapshot) {
 }
 
 impl Data {
+    /// Empty if there is no valid file, the backup and unreadable files
+    /// are handled like in the server's `load_json`
     pub async fn load(path: impl AsRef<std::path::Path>) -> Self {
-        async fn load(path: impl AsRef<std::path::Path>) -> anyhow::Result<Data> {
-            let bytes = file::load_bytes(path).await?;
-            Ok(bincode::deserialize(&bytes)?)
+        let path = path.as_ref();
+        if path.exists() {
+            match Self::try_load(path).await {
+                Ok(data) => return data,
+                Err(e) => error!("Failed to load {path:?}: {e:?}"),
+            }
+        }
+        let backup = backup_path(path);
+        let data = match Self::try_load(&backup).await {
+            Ok(data) => {
+                warn!("Loaded bots from the backup {backup:?}");
+                Some(data)
+            }
+            Err(_) => None,
+        };
+        if path.exists() {
+            move_aside(path);
         }
-        let mut data = load(path).await.unwrap_or(Self(default()));
-        for data in data.0.values_mut() {
+        data.unwrap_or(Self(default()))
+    }
+
+    pub async fn try_load(path: impl AsRef<std::path::Path>) -> anyhow::Result<Self> {
+        Self::from_bytes(&file::load_bytes(path).await?)
+    }
+
+    /// Reads every format version there was
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        use bincode::Options;
+        fn convert<T: Into<MoveData>>(data: HashMap<Track, Vec<T>>) -> Data {
+            Data(
+                data.into_iter()
+                    .map(|(track, replays)| (track, replays.into_iter().map(Into::into).collect()))
+                    .collect(),
+            )
+        }
+        // Same encoding as bincode::serialize, but the whole file has to match the layout
+        let options = bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .reject_trailing_bytes();
+        let mut data = match bytes.strip_prefix(MAGIC.as_slice()) {
+            Some(rest) => {
+                if rest.len() < 4 {
+                    anyhow::bail!("Truncated header");
+                }
+                let (version, body) = rest.split_at(4);
+                let version = u32::from_le_bytes(version.try_into().unwrap());
+                match version {
+                    FORMAT_VERSION => options.deserialize::<Data>(body)?,
+                    _ => anyhow::bail!(
+                        "Format version {version} is not supported, expected {FORMAT_VERSION}"
+                    ),
+                }
+            }
+            None => {
+                if let Ok(data) = options.deserialize::<legacy::v2::Data>(bytes) {
+                    convert(data.0)
+                } else if let Ok(data) = options.deserialize::<legacy::v1::Data>(bytes) {
+                    convert(data.0)
+                } else {
+                    convert(options.deserialize::<legacy::v0::Data>(bytes)?.0)
+                }
+            }
+        };
+        data.repair();
+        Ok(data)
+    }
+
+    /// Drop empty replays and values that can not be replayed, for data from files
+    pub fn repair(&mut self) {
+        for data in self.0.values_mut() {
+            data.retain(|data| !data.data.is_empty());
             for data in data {
                 for data in &mut data.data {
                     let data = &mut data.data;
@@ -104,7 +371,72 @@ impl Data {
                 }
             }
         }
-        data
+    }
+
+    /// Header and the data in the latest format
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bincode::serialize_into(&mut bytes, self).unwrap();
+        bytes
+    }
+
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+        write_atomic(path, &self.to_bytes())
+    }
+
+    /// Drop replays that do not go from start to finish, returns how many were removed
+    pub fn retain_valid(&mut self, level: &Level, config: &Config) -> usize {
+        let mut removed = 0;
+        self.0.retain(|track, data| {
+            // Files from another level can have tracks this one does not
+            let (from, to) = match (
+                level.cat_locations.get(track.from),
+                level.cat_locations.get(track.to),
+            ) {
+                (Some(&from), Some(&to)) => (from, to),
+                _ => {
+                    removed += data.len();
+                    return false;
+                }
+            };
+            let len = data.len();
+            data.retain(|data| match (data.data.first(), data.data.last()) {
+                (Some(first), Some(last)) => {
+                    (first.data.pos - from).len() < config.player_radius * 2.0
+                        && (last.data.pos - to).len() < config.player_radius * 2.0
+                }
+                _ => false,
+            });
+            removed += len - data.len();
+            true
+        });
+        removed
+    }
+
+    /// Add replays that are not here yet, returns how many were added
+    pub fn merge(&mut self, other: Data) -> usize {
+        fn key(replay: &MoveData) -> (Option<u64>, usize, u32, [u32; 2]) {
+            let result = replay.result();
+            (
+                replay.date,
+                replay.data.len(),
+                result.time.to_bits(),
+                [result.pos.x.to_bits(), result.pos.y.to_bits()],
+            )
+        }
+        let mut added = 0;
+        for (track, replays) in other.0 {
+            let data = self.0.entry(track).or_default();
+            let mut known: HashSet<_> = data.iter().map(key).collect();
+            for replay in replays {
+                if !replay.data.is_empty() && known.insert(key(&replay)) {
+                    data.push(replay);
+                    added += 1;
+                }
+            }
+        }
+        added
     }
 
     pub fn push(&mut self, track: Track, replay: MoveData) {
@@ -114,11 +446,27 @@ impl Data {
         self.0.entry(track).or_default().push(replay);
     }
 
-    pub fn get(&self, track: Track, time: f32) -> impl Iterator<Item = Player> + '_ {
-        self.0
-            .get(&track)
+    pub fn replay(&self, track: Track, index: usize) -> Option<&MoveData> {
+        self.0.get(&track)?.get(index)
+    }
+
+    /// Recordings for up to `count` bots with finish times closest to the difficulty
+    pub fn pick(&self, track: Track, difficulty: BotDifficulty, count: usize) -> Vec<usize> {
+        let data = match self.0.get(&track) {
+            Some(data) => data,
+            None => return vec![],
+        };
+        let mut by_time: Vec<usize> = (0..data.len()).collect();
+        by_time.sort_by_key(|&index| r32(data[index].result().time));
+        let target = difficulty.percentile() / 100.0;
+        let distance = |rank: usize| ((rank as f32 + 0.5) / data.len() as f32 - target).abs();
+        let mut ranks: Vec<usize> = (0..data.len()).collect();
+        ranks.sort_by_key(|&rank| r32(distance(rank)));
+        ranks
             .into_iter()
-            .flat_map(move |data| data.iter().map(move |data| data.get(time)))
+            .take(count)
+            .map(|rank| by_time[rank])
+            .collect()
     }
 
     pub fn max_bots(&self) -> usize {
@@ -132,3 +480,157 @@ impl Data {
             .flat_map(move |data| data.iter().map(move |data| data.result()))
     }
 }
+
+#[test]
+fn test_pick_by_difficulty() {
+    let track = Track { from: 0, to: 1 };
+    let mut data = Data(default());
+    // Finish times 1 to 9 in shuffled order
+    for time in [5, 2, 8, 1, 9, 4, 7, 3, 6] {
+        let mut replay = MoveData::new();
+        let player = Player {
+            color: 0.0,
+            skin: 0,
+            pos: vec2::ZERO,
+            vel: vec2::ZERO,
+            rot: 0.0,
+        };
+        replay.push(0.0, player.clone());
+        replay.push(time as f32, player);
+        data.push(track, replay);
+    }
+    let times = |difficulty, count| -> Vec<i32> {
+        let mut times: Vec<i32> = data
+            .pick(track, difficulty, count)
+            .into_iter()
+            .map(|index| data.0[&track][index].result().time as i32)
+            .collect();
+        times.sort();
+        times
+    };
+    assert_eq!(times(BotDifficulty::Hard, 3), [1, 2, 3]);
+    assert_eq!(times(BotDifficulty::Normal, 3), [4, 5, 6]);
+    assert_eq!(times(BotDifficulty::Easy, 3), [7, 8, 9]);
+    assert_eq!(times(BotDifficulty::Percentile(0.0), 1), [1]);
+    assert_eq!(times(BotDifficulty::Easy, 20).len(), 9);
+    assert!(data.pick(Track { from: 1, to: 0 }, BotDifficulty::Hard, 3).is_empty());
+}
+
+#[test]
+fn test_merge_skips_duplicates() {
+    let track = Track { from: 0, to: 1 };
+    let replay = |time: f32| {
+        let mut replay = MoveData::new();
+        let player = Player {
+            color: 0.0,
+            skin: 0,
+            pos: vec2::ZERO,
+            vel: vec2::ZERO,
+            rot: 0.0,
+        };
+        replay.push(0.0, player.clone());
+        replay.push(time, player);
+        replay
+    };
+    let mut data = Data(default());
+    data.push(track, replay(1.0));
+    let mut other = Data(default());
+    other.push(track, replay(1.0));
+    other.push(track, replay(2.0));
+    other.push(Track { from: 1, to: 0 }, replay(3.0));
+    assert_eq!(data.merge(other), 2);
+    assert_eq!(data.0[&track].len(), 2);
+    assert_eq!(data.0.len(), 2);
+}
+
+#[test]
+fn test_retain_valid() {
+    let level: Level = serde_json::from_str(include_str!("../level.json")).unwrap();
+    let config = physics::test_config();
+    let replay = |from: usize, to: usize| {
+        let mut replay = MoveData::new();
+        for (time, pos) in [
+            (0.0, level.cat_locations[from]),
+            (1.0, level.cat_locations[to]),
+        ] {
+            replay.push(
+                time,
+                Player {
+                    color: 0.0,
+                    skin: 0,
+                    pos,
+                    vel: vec2::ZERO,
+                    rot: 0.0,
+                },
+            );
+        }
+        replay
+    };
+    let track = Track { from: 0, to: 1 };
+    let mut data = Data(default());
+    data.push(track, replay(0, 1));
+    data.push(track, replay(1, 0));
+    // Only files written by hand can have empty replays
+    data.0.get_mut(&track).unwrap().push(MoveData::new());
+    data.push(
+        Track {
+            from: 0,
+            to: level.cat_locations.len(),
+        },
+        replay(0, 1),
+    );
+    assert_eq!(data.retain_valid(&level, &config), 3);
+    assert_eq!(data.0.len(), 1);
+    assert_eq!(data.0[&track].len(), 1);
+}
+
+#[test]
+fn test_format_versions() {
+    let track = Track { from: 0, to: 1 };
+    let mut replay = MoveData::new();
+    replay.push(
+        1.0,
+        Player {
+            color: 0.5,
+            skin: 2,
+            pos: vec2(1.0, 2.0),
+            vel: vec2::ZERO,
+            rot: 0.0,
+        },
+    );
+    replay.date = Some(123);
+
+    let mut data = Data(default());
+    data.push(track, replay.clone());
+    let loaded = Data::from_bytes(&data.to_bytes()).unwrap();
+    let loaded = &loaded.0[&track][0];
+    assert_eq!(loaded.driver, Some((2, 0.5)));
+    assert_eq!(loaded.date, Some(123));
+
+    // Version 0 had nothing but the movement and no header
+    let v0 = bincode::serialize(&HashMap::from([(track, vec![replay.data.clone()])])).unwrap();
+    let loaded = Data::from_bytes(&v0).unwrap();
+    let loaded = &loaded.0[&track][0];
+    assert_eq!(loaded.data[0].data.pos, vec2(1.0, 2.0));
+    assert_eq!(loaded.driver, None);
+    assert_eq!(loaded.date, None);
+
+    let mut future = MAGIC.to_vec();
+    future.extend_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+    assert!(Data::from_bytes(&future).is_err());
+}
+
+#[test]
+fn test_write_atomic() {
+    let dir = std::env::temp_dir().join(format!("coots-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("bots.data");
+    write_atomic(&path, b"first").unwrap();
+    write_atomic(&path, b"second").unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), b"second");
+    assert_eq!(std::fs::read(backup_path(&path)).unwrap(), b"first");
+    write_atomic(&path, b"third").unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), b"third");
+    assert_eq!(std::fs::read(backup_path(&path)).unwrap(), b"second");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,171 @@
+use super::*;
+
+use std::path::{Path, PathBuf};
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Defaults to bots.data next to the server
+    #[clap(long)]
+    pub data: Option<PathBuf>,
+    #[clap(subcommand)]
+    pub command: Command,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum Command {
+    /// Print replay counts and finish times per track
+    Stats,
+    /// Write replays as JSON
+    Export {
+        /// Only this track, like 0-3
+        #[clap(long, value_parser = parse_track)]
+        track: Option<Track>,
+        /// Prints to stdout if not set
+        #[clap(long)]
+        output: Option<PathBuf>,
+    },
+    /// Merge replays from other bots.data files or exported JSON, stop the server first
+    Import {
+        #[clap(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Remove replays, stop the server first
+    Prune {
+        /// Keep only this many most recent replays per track
+        #[clap(long)]
+        max_per_track: Option<usize>,
+        /// Remove replays recorded more than this many days ago
+        #[clap(long)]
+        older_than_days: Option<f64>,
+        /// Remove replays that do not go from start to finish of their track
+        #[clap(long)]
+        invalid: bool,
+    },
+}
+
+fn parse_track(s: &str) -> std::result::Result<Track, String> {
+    let usage = || format!("{s:?} is not a track like 0-3");
+    let (from, to) = s.split_once('-').ok_or_else(usage)?;
+    Ok(Track {
+        from: from.trim().parse().map_err(|_| usage())?,
+        to: to.trim().parse().map_err(|_| usage())?,
+    })
+}
+
+/// Export format, JSON can not have tracks as map keys
+#[derive(Serialize, Deserialize)]
+struct TrackReplays {
+    track: Track,
+    replays: Vec<MoveData>,
+}
+
+fn load(path: &Path) -> anyhow::Result<Data> {
+    if path.extension().map_or(false, |ext| ext == "json") {
+        let tracks: Vec<TrackReplays> = serde_json::from_reader(std::fs::File::open(path)?)?;
+        let mut data = Data(default());
+        for TrackReplays { track, replays } in tracks {
+            data.0.entry(track).or_default().extend(replays);
+        }
+        data.repair();
+        Ok(data)
+    } else {
+        futures::executor::block_on(Data::try_load(path))
+    }
+}
+
+fn count(data: &Data) -> usize {
+    data.0.values().map(|replays| replays.len()).sum()
+}
+
+pub fn run(args: Args) -> anyhow::Result<()> {
+    let path = args.data.unwrap_or_else(|| run_dir().join("bots.data"));
+    let mut data = if path.exists() {
+        load(&path)?
+    } else {
+        Data(default())
+    };
+    match args.command {
+        Command::Stats => {
+            let mut tracks: Vec<_> = data
+                .0
+                .iter()
+                .filter(|(_, replays)| !replays.is_empty())
+                .collect();
+            tracks.sort_by_key(|(track, _)| (track.from, track.to));
+            println!("track   replays  fastest   median  slowest  dated");
+            for (track, replays) in tracks {
+                let mut times: Vec<f32> =
+                    replays.iter().map(|replay| replay.result().time).collect();
+                times.sort_by_key(|&time| r32(time));
+                println!(
+                    "{:>2} -> {:<2} {:>7} {:>7.1}s {:>7.1}s {:>7.1}s {:>6}",
+                    track.from,
+                    track.to,
+                    replays.len(),
+                    times[0],
+                    times[times.len() / 2],
+                    times[times.len() - 1],
+                    replays.iter().filter(|replay| replay.date.is_some()).count(),
+                );
+            }
+            println!("{} replays total", count(&data));
+        }
+        Command::Export { track, output } => {
+            let mut tracks: Vec<TrackReplays> = data
+                .0
+                .into_iter()
+                .filter(|(other, _)| track.map_or(true, |track| track == *other))
+                .map(|(track, replays)| TrackReplays { track, replays })
+                .collect();
+            tracks.sort_by_key(|entry| (entry.track.from, entry.track.to));
+            match output {
+                Some(output) => {
+                    let file = std::io::BufWriter::new(std::fs::File::create(&output)?);
+                    serde_json::to_writer(file, &tracks)?;
+                    eprintln!("Exported {} tracks to {output:?}", tracks.len());
+                }
+                None => serde_json::to_writer(std::io::stdout().lock(), &tracks)?,
+            }
+        }
+        Command::Import { files } => {
+            for file in files {
+                let added = data.merge(load(&file)?);
+                println!("Added {added} replays from {file:?}");
+            }
+            data.save(&path)?;
+            println!("{} replays total", count(&data));
+        }
+        Command::Prune {
+            max_per_track,
+            older_than_days,
+            invalid,
+        } => {
+            let before = count(&data);
+            if invalid {
+                let level: Level =
+                    serde_json::from_reader(std::fs::File::open(run_dir().join("level.json"))?)?;
+                let config: Config =
+                    serde_json::from_reader(std::fs::File::open(run_dir().join("config.json"))?)?;
+                data.retain_valid(&level, &config);
+            }
+            if let Some(days) = older_than_days {
+                let cutoff = now().saturating_sub((days * 24.0 * 60.0 * 60.0) as u64);
+                for replays in data.0.values_mut() {
+                    replays.retain(|replay| replay.date.map_or(true, |date| date >= cutoff));
+                }
+            }
+            if let Some(max) = max_per_track {
+                for replays in data.0.values_mut() {
+                    // Unknown dates count as the oldest
+                    replays.sort_by_key(|replay| std::cmp::Reverse(replay.date));
+                    replays.truncate(max);
+                }
+            }
+            data.0.retain(|_, replays| !replays.is_empty());
+            data.save(&path)?;
+            let after = count(&data);
+            println!("Removed {} replays, {after} left", before - after);
+        }
+    }
+    Ok(())
+}
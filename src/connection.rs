@@ -0,0 +1,109 @@
+use super::*;
+
+use futures::FutureExt;
+use std::{future::Future, pin::Pin};
+
+pub type Connection = geng::net::client::Connection<ServerMessage, ClientMessage>;
+
+/// Delay between reconnection attempts
+const RECONNECT_INTERVAL: f32 = 1.0;
+
+type Attempt = Pin<Box<dyn Future<Output = Option<Connection>>>>;
+
+/// Connection to the server that reconnects when dropped
+/// and resumes the session using the token issued by the server
+pub struct ServerConnection {
+    /// Never connects if [None]
+    addr: Option<String>,
+    connection: Option<Connection>,
+    attempt: Option<Attempt>,
+    next_attempt: f32,
+    resume_token: Option<String>,
+}
+
+impl ServerConnection {
+    pub fn new(addr: String, connection: Connection) -> Self {
+        Self {
+            addr: Some(addr),
+            connection: Some(connection),
+            attempt: None,
+            next_attempt: 0.0,
+            resume_token: None,
+        }
+    }
+
+    /// Drops everything sent, for watching match recordings
+    pub fn offline() -> Self {
+        Self {
+            addr: None,
+            connection: None,
+            attempt: None,
+            next_attempt: 0.0,
+            resume_token: None,
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connection.is_some()
+    }
+
+    pub fn set_resume_token(&mut self, token: String) {
+        self.resume_token = Some(token);
+    }
+
+    /// Messages sent while disconnected are dropped
+    pub fn send(&mut self, message: ClientMessage) {
+        if let Some(connection) = &mut self.connection {
+            connection.send(message);
+        }
+    }
+
+    pub fn try_recv(&mut self) -> Option<ServerMessage> {
+        match self.connection.as_mut()?.try_recv()? {
+            Ok(message) => Some(message),
+            Err(e) => {
+                warn!("Lost connection to the server: {e}");
+                self.connection = None;
+                self.next_attempt = 0.0;
+                None
+            }
+        }
+    }
+
+    /// Returns true if connection was just reestablished
+    pub fn update(&mut self, delta_time: f32) -> bool {
+        if self.connection.is_some() {
+            return false;
+        }
+        if let Some(attempt) = &mut self.attempt {
+            match attempt.as_mut().now_or_never() {
+                Some(Some(connection)) => {
+                    info!("Reconnected to the server");
+                    self.attempt = None;
+                    self.connection = Some(connection);
+                    if let Some(token) = self.resume_token.clone() {
+                        self.send(ClientMessage::Resume(token));
+                    }
+                    return true;
+                }
+                Some(None) => {
+                    self.attempt = None;
+                    self.next_attempt = RECONNECT_INTERVAL;
+                }
+                None => {}
+            }
+            return false;
+        }
+        self.next_attempt -= delta_time;
+        if self.next_attempt < 0.0 {
+            let addr = match self.addr.clone() {
+                Some(addr) => addr,
+                None => return false,
+            };
+            self.attempt = Some(Box::pin(async move {
+                geng::net::client::connect(&addr).await.ok()
+            }));
+        }
+        false
+    }
+}
@@ -1,19 +1,65 @@
 use super::*;
 
 const SNAP_DISTANCE: f32 = 0.2;
+const ROOMS_REFRESH_INTERVAL: f32 = 2.0;
+const GHOST_ALPHA: f32 = 0.25;
+/// Seconds skipped by seeking in replays
+const REPLAY_SEEK: f32 = 5.0;
+const CHAT_HISTORY: usize = 8;
+/// Seconds chat messages stay on screen while not typing
+const CHAT_LINE_TIME: f32 = 10.0;
+const CHAT_TEXT_SIZE: f32 = 0.5;
+/// Seconds an emote stays above the car
+const EMOTE_TIME: f32 = 2.5;
+const EMOTE_POP_TIME: f32 = 0.15;
+const EMOTE_FADE_TIME: f32 = 0.5;
+const EMOTE_SIZE: f32 = 0.6;
+const PATH_UPDATE_INTERVAL: f32 = 0.25;
+const BREADCRUMB_SPACING: f32 = 1.0;
+const BREADCRUMB_SPEED: f32 = 2.0;
+const BREADCRUMB_RADIUS: f32 = 0.15;
+const BREADCRUMB_ALPHA: f32 = 0.5;
+
+/// Number keys from 1 pick emotes
+fn emote_key(key: geng::Key) -> Option<usize> {
+    Some(match key {
+        geng::Key::Num1 => 0,
+        geng::Key::Num2 => 1,
+        geng::Key::Num3 => 2,
+        geng::Key::Num4 => 3,
+        geng::Key::Num5 => 4,
+        geng::Key::Num6 => 5,
+        geng::Key::Num7 => 6,
+        geng::Key::Num8 => 7,
+        geng::Key::Num9 => 8,
+        _ => return None,
+    })
+}
+
+/// Formats unix timestamp as YYYY-MM-DD
+fn format_date(timestamp: u64) -> String {
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = (timestamp / 86400) as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{year:04}-{month:02}-{day:02}")
+}
 
-fn vector_from(p: vec2<f32>, p1: vec2<f32>, p2: vec2<f32>) -> vec2<f32> {
-    if vec2::dot(p - p1, p2 - p1) < 0.0 {
-        return p1 - p;
+fn format_record(record: &Record) -> String {
+    let mut text = format!("record {:.2}s", record.time);
+    if !record.name.is_empty() {
+        text += &format!(" by {}", record.name);
     }
-    if vec2::dot(p - p2, p1 - p2) < 0.0 {
-        return p2 - p;
+    if record.date != 0 {
+        text += &format!(" on {}", format_date(record.date));
     }
-    let n = (p2 - p1).rotate_90();
-    // dot(p + n * t - p1, n) = 0
-    // dot(p - p1, n) + dot(n, n) * t = 0
-    let t = vec2::dot(p1 - p, n) / vec2::dot(n, n);
-    n * t
+    text
 }
 
 impl Level {
@@ -35,7 +81,7 @@ impl Level {
     pub fn hovered_segment(&self, pos: vec2<f32>) -> Option<usize> {
         self.segments
             .iter()
-            .position(|&[p1, p2]| vector_from(pos, p1, p2).len() < SNAP_DISTANCE)
+            .position(|&[p1, p2]| physics::vector_from(pos, p1, p2).len() < SNAP_DISTANCE)
     }
 }
 
@@ -161,12 +207,7 @@ async fn load_player_assets(
     .collect()
 }
 
-pub struct PlayerInput {
-    rotate: f32,     // -1 .. 1
-    accelerate: f32, // -1 .. 1
-}
-
-type Connection = geng::net::client::Connection<ServerMessage, ClientMessage>;
+use connection::ServerConnection;
 
 struct RemotePlayer {
     skin: usize,
@@ -206,6 +247,21 @@ impl RemotePlayer {
     }
 }
 
+struct ChatLine {
+    name: String,
+    text: String,
+    /// Seconds since it was received
+    age: f32,
+}
+
+/// Watching a match recording instead of playing
+struct Replay {
+    playback: match_recording::Playback,
+    /// Player the camera follows, free camera if [None]
+    follow: Option<Id>,
+    fov: f32,
+}
+
 struct Particle {
     pos: vec2<f32>,
     vel: vec2<f32>,
@@ -271,7 +327,7 @@ pub struct Game {
     color: f32,
     assets: Rc<Assets>,
     config: Rc<Config>,
-    connection: Connection,
+    connection: ServerConnection,
     player: Option<Player>,
     camera: geng::Camera2d,
     level: Level,
@@ -289,6 +345,8 @@ pub struct Game {
     round: Round,
     numbers: Numbers,
     name: String,
+    /// Told by the server after joining a room
+    player_id: Option<Id>,
     spectating: bool,
     music_on: bool,
     music_menu: bool,
@@ -307,6 +365,42 @@ pub struct Game {
     gilrs: gilrs::Gilrs,
     active_gamepad: Option<gilrs::GamepadId>,
     round_countdown: f32,
+    next_input: u64,
+    sent_inputs: u64,
+    pending_inputs: VecDeque<(u64, PlayerInput)>,
+    last_snapshot_time: f32,
+    quantizer: snapshot::Quantizer,
+    snapshots: snapshot::Decoder,
+    room: Option<RoomId>,
+    rooms: Vec<RoomInfo>,
+    rooms_refresh: f32,
+    /// Room to join once its password is typed, with the password so far
+    password_prompt: Option<(RoomId, String)>,
+    leaderboard: Vec<(String, PlayerStats)>,
+    track_record: Option<TrackRecord>,
+    /// Time since the current round started
+    round_time: Option<f32>,
+    /// Results of the last round, shown until the next one starts
+    round_results: Option<RoundResults>,
+    navigation: navigation::Navigation,
+    /// Qualified in the current round in order, [None] is me
+    qualified: Vec<Option<Id>>,
+    /// Arrow follows the shortest path instead of pointing straight at Coots
+    path_arrow: bool,
+    breadcrumbs: bool,
+    /// Shortest path from my car to Coots
+    target_path: Vec<vec2<f32>>,
+    next_path_update: f32,
+    replay: Option<Replay>,
+    chat: VecDeque<ChatLine>,
+    /// Message being typed, chat is closed if [None]
+    chat_input: Option<String>,
+    /// Lowercase names of players whose messages are hidden
+    muted: HashSet<String>,
+    /// Emotes shown above cars with seconds since they were sent, [None] is me
+    emotes: HashMap<Option<Id>, (usize, f32)>,
+    /// Shown until the game is restarted, like when the server is incompatible
+    error: Option<String>,
 }
 
 impl Game {
@@ -359,7 +453,7 @@ impl Game {
         assets: &Rc<Assets>,
         level: Level,
         config: &Rc<Config>,
-        mut connection: Connection,
+        mut connection: ServerConnection,
         args: Args,
     ) -> Self {
         connection.send(ClientMessage::Ping);
@@ -378,6 +472,15 @@ impl Game {
             preferences::save("color", &color);
             color
         });
+        connection.send(ClientMessage::Appearance { skin, color });
+        if let Some(room) = &args.room {
+            connection.send(ClientMessage::JoinRoom {
+                id: room.clone(),
+                password: args.room_password.clone(),
+            });
+        }
+        let quantizer = snapshot::Quantizer::new(&level, config);
+        let navigation = navigation::Navigation::new(&level, config);
         Self {
             spectate_zoomed_in: false,
             ready: false,
@@ -445,6 +548,7 @@ impl Game {
             },
             names: default(),
             name,
+            player_id: None,
             drift_sfx: {
                 let mut effect = assets.sfx.drift.effect();
                 effect.set_volume(0.0);
@@ -468,133 +572,557 @@ impl Game {
             telecam: true,
             gilrs: gilrs::Gilrs::new().unwrap(),
             round_countdown: 0.0,
+            next_input: 0,
+            sent_inputs: 0,
+            pending_inputs: default(),
+            last_snapshot_time: 0.0,
+            quantizer,
+            snapshots: default(),
+            room: None,
+            rooms: vec![],
+            rooms_refresh: 0.0,
+            password_prompt: None,
+            leaderboard: vec![],
+            track_record: None,
+            round_time: None,
+            round_results: None,
+            navigation,
+            qualified: vec![],
+            path_arrow: preferences::load("path_arrow").unwrap_or(false),
+            breadcrumbs: preferences::load("breadcrumbs").unwrap_or(false),
+            target_path: vec![],
+            next_path_update: 0.0,
+            replay: None,
+            chat: default(),
+            chat_input: None,
+            muted: preferences::load("muted").unwrap_or_default(),
+            emotes: default(),
+            error: None,
         }
     }
 
-    fn update_connection(&mut self) {
+    /// Play back the recording instead of the server
+    pub fn watch(&mut self, recording: match_recording::Recording) {
+        self.replay = Some(Replay {
+            playback: match_recording::Playback::new(recording),
+            follow: None,
+            fov: self.config.map_scale * 2.0,
+        });
+        self.in_settings = false;
+    }
+
+    fn update_replay(&mut self) {
+        while let Some(event) = self
+            .replay
+            .as_mut()
+            .and_then(|replay| replay.playback.next_event())
+        {
+            // Events skipped over by seeking are late, keep the round timers right
+            let late = self.replay.as_ref().unwrap().playback.time - event.time;
+            let round_started = matches!(event.message, ServerMessage::RoundStarted);
+            self.handle_message(event.message);
+            if round_started {
+                self.round_time = Some(late);
+                self.cat_move_time -= late;
+            }
+        }
+    }
+
+    fn update_replay_camera(&mut self, delta_time: f32) {
+        self.drift_sfx.set_volume(0.0);
+        self.forward_sfx.set_volume(0.0);
+        let replay = match &self.replay {
+            Some(replay) => replay,
+            None => return,
+        };
+        let followed = replay
+            .follow
+            .and_then(|id| self.remote_players.get(&id))
+            .map(|player| player.get().pos);
+        if let Some(pos) = followed {
+            self.camera.center +=
+                (pos - self.camera.center) * (self.config.camera_speed * delta_time).min(1.0);
+        } else if replay.follow.is_none() {
+            let window = self.geng.window();
+            let mut dir = vec2::ZERO;
+            if window.is_key_pressed(geng::Key::A) {
+                dir.x -= 1.0;
+            }
+            if window.is_key_pressed(geng::Key::D) {
+                dir.x += 1.0;
+            }
+            if window.is_key_pressed(geng::Key::S) {
+                dir.y -= 1.0;
+            }
+            if window.is_key_pressed(geng::Key::W) {
+                dir.y += 1.0;
+            }
+            self.camera.center += dir * self.camera.fov * delta_time;
+            self.camera.center = self.camera.center.clamp_aabb(Aabb2::points_bounding_box(
+                self.level.segments.iter().copied().flatten(),
+            ));
+        }
+        self.camera.fov +=
+            (replay.fov - self.camera.fov) * (self.config.zoom_speed * delta_time).min(1.0);
+    }
+
+    fn handle_replay_key(&mut self, key: geng::Key) {
+        let replay = match &mut self.replay {
+            Some(replay) => replay,
+            None => return,
+        };
+        let playback = &mut replay.playback;
+        match key {
+            geng::Key::Space => playback.paused = !playback.paused,
+            geng::Key::Left => playback.seek(playback.time - REPLAY_SEEK),
+            geng::Key::Right => playback.seek(playback.time + REPLAY_SEEK),
+            geng::Key::Home => playback.seek(0.0),
+            geng::Key::Up => playback.change_speed(true),
+            geng::Key::Down => playback.change_speed(false),
+            geng::Key::C => {
+                replay.follow = match replay.follow {
+                    Some(_) => None,
+                    None => self.remote_players.keys().min().copied(),
+                };
+                replay.fov = match replay.follow {
+                    Some(_) => self.config.camera_fov,
+                    None => self.config.map_scale * 2.0,
+                };
+            }
+            geng::Key::Tab => {
+                let mut ids: Vec<Id> = self.remote_players.keys().copied().collect();
+                ids.sort();
+                replay.follow = ids
+                    .iter()
+                    .find(|&&id| replay.follow.map_or(true, |follow| id > follow))
+                    .or(ids.first())
+                    .copied();
+                replay.fov = self.config.camera_fov;
+            }
+            _ => {}
+        }
+    }
+
+    /// Shown instead of the hint at the bottom
+    fn replay_status(&self, replay: &Replay) -> String {
+        let playback = &replay.playback;
+        let format_time = |time: f32| {
+            let seconds = time as u32;
+            format!("{}:{:02}", seconds / 60, seconds % 60)
+        };
+        let camera = match replay.follow {
+            Some(id) => format!(
+                "following {}",
+                self.names.get(&id).map_or("<noname>", |name| name.as_str())
+            ),
+            None => "free camera".to_owned(),
+        };
+        format!(
+            "{} / {}  {}x{}\n{camera}",
+            format_time(playback.time),
+            format_time(playback.duration()),
+            playback.speed,
+            if playback.paused { "  paused" } else { "" },
+        )
+    }
+
+    fn update_connection(&mut self, delta_time: f32) {
+        let was_connected = self.connection.is_connected();
+        if self.connection.update(delta_time) {
+            self.text2 = Some(("Reconnected".to_owned(), -2.0));
+            self.connection.send(ClientMessage::Ping);
+            self.connection.send(ClientMessage::Name(self.name.clone()));
+            self.connection.send(ClientMessage::Appearance {
+                skin: self.skin,
+                color: self.color,
+            });
+            self.connection.send(ClientMessage::Ready(self.ready));
+            // Whatever was sent right before the drop may never have arrived
+            self.sent_inputs = self.oldest_pending_input();
+        }
         while let Some(message) = self.connection.try_recv() {
-            let message = message.unwrap();
-            match &message {
-                ServerMessage::Pong => {}
-                ServerMessage::UpdatePlayer(..) => {}
-                _ => debug!("{message:?}"),
-            }
-            match message {
-                ServerMessage::YourName(name) => {
-                    self.name = name;
+            self.handle_message(message);
+        }
+        if was_connected && !self.connection.is_connected() {
+            self.text2 = Some(("Connection lost, reconnecting...".to_owned(), -2.0));
+        }
+    }
+
+    fn handle_message(&mut self, message: ServerMessage) {
+        match &message {
+            ServerMessage::Pong => {}
+            ServerMessage::Snapshot(..) => {}
+            ServerMessage::Reconcile(..) => {}
+            _ => debug!("{message:?}"),
+        }
+        match message {
+            ServerMessage::Hello {
+                protocol,
+                level_checksum,
+                server_authoritative,
+            } => {
+                if protocol != PROTOCOL_VERSION {
+                    error!("Server protocol is {protocol}, expected {PROTOCOL_VERSION}");
+                    self.error = Some(format!(
+                        "This game does not match the server version\n\
+                         (protocol {PROTOCOL_VERSION}, server has {protocol})\n\
+                         please update or reload the game"
+                    ));
+                    self.connection = ServerConnection::offline();
+                    return;
                 }
-                ServerMessage::Name(id, name) => {
-                    self.names.insert(id, name);
+                Rc::make_mut(&mut self.config).server_authoritative = server_authoritative;
+                if level_checksum != self.level.checksum() && !self.args.editor {
+                    info!("Level differs from the server, downloading it");
+                    self.connection.send(ClientMessage::DownloadLevel);
                 }
-                ServerMessage::Pong => {
-                    self.connection.send(ClientMessage::Ping);
-                    if let Some(player) = &self.player {
-                        if self.practice.is_none() {
-                            self.connection
-                                .send(ClientMessage::UpdatePlayer(player.clone()));
-                        }
+            }
+            ServerMessage::Level(level) => {
+                self.set_level(level);
+            }
+            ServerMessage::YourName(name) => {
+                self.name = name;
+            }
+            ServerMessage::Name(id, name) => {
+                self.names.insert(id, name);
+            }
+            ServerMessage::Pong => {
+                self.connection.send(ClientMessage::Ping);
+                if let Some(player) = &self.player {
+                    // Inputs are sent every frame instead
+                    if self.practice.is_none() && !self.config.server_authoritative {
+                        self.connection
+                            .send(ClientMessage::UpdatePlayer(player.clone()));
                     }
                 }
-                ServerMessage::UpdatePlayer(id, player) => match player {
-                    Some(player) => match self.remote_players.entry(id) {
-                        std::collections::hash_map::Entry::Occupied(mut entry) => {
-                            entry.get_mut().server_update(player);
-                        }
-                        std::collections::hash_map::Entry::Vacant(entry) => {
-                            entry.insert(RemotePlayer::new(player));
-                        }
-                    },
-                    None => {
-                        self.remote_players.remove(&id);
-                    }
-                },
-                ServerMessage::Disconnect(id) => {
-                    self.remote_players.remove(&id);
+            }
+            ServerMessage::Reconcile(ack, player) => {
+                self.reconcile(ack, player);
+            }
+            ServerMessage::ResendInputs(from) => {
+                self.resend_inputs(from);
+            }
+            ServerMessage::Snapshot(snapshot) => {
+                self.apply_snapshot(snapshot);
+            }
+            ServerMessage::Disconnect(id) => {
+                self.remote_players.remove(&id);
+                self.emotes.remove(&Some(id));
+            }
+            ServerMessage::YouHaveBeenEliminated => {
+                if !self.args.editor && self.practice.is_none() {
+                    self.player = None;
+                    self.text2 = Some(("You have been eliminated".to_owned(), -2.0));
+                    self.spectating = true;
+                    self.spectate_zoomed_in = false;
+                    self.assets.sfx.eliminated.play();
                 }
-                ServerMessage::YouHaveBeenEliminated => {
-                    if !self.args.editor && self.practice.is_none() {
-                        self.player = None;
-                        self.text2 = Some(("You have been eliminated".to_owned(), -2.0));
-                        self.spectating = true;
-                        self.spectate_zoomed_in = false;
-                        self.assets.sfx.eliminated.play();
-                    }
+            }
+            ServerMessage::YouHaveBeenRespawned(pos) => {
+                if !self.args.editor && self.practice.is_none() {
+                    self.player = Some(Player {
+                        skin: self.skin,
+                        color: self.color,
+                        pos,
+                        vel: vec2::ZERO,
+                        rot: thread_rng().gen_range(0.0..2.0 * f32::PI),
+                    });
+                    self.spectating = false;
+                    self.assets.sfx.new_round.play();
+                    // self.text = Some(("GO".to_owned(), 0.0));
                 }
-                ServerMessage::YouHaveBeenRespawned(pos) => {
-                    if !self.args.editor && self.practice.is_none() {
-                        self.player = Some(Player {
-                            skin: self.skin,
-                            color: self.color,
-                            pos,
-                            vel: vec2::ZERO,
-                            rot: thread_rng().gen_range(0.0..2.0 * f32::PI),
-                        });
-                        self.spectating = false;
-                        self.assets.sfx.new_round.play();
-                        // self.text = Some(("GO".to_owned(), 0.0));
+            }
+            ServerMessage::YouHaveBeenCorrected(pos) => {
+                if !self.args.editor && self.practice.is_none() {
+                    if let Some(player) = &mut self.player {
+                        player.pos = pos;
+                        player.vel = vec2::ZERO;
                     }
                 }
-                ServerMessage::RoundStarted => {
-                    self.cat_move_time = self.config.cat_move_time as f32;
+            }
+            ServerMessage::RoundStarted => {
+                self.cat_move_time = self.config.cat_move_time as f32;
+                self.round_time = Some(0.0);
+                self.round_results = None;
+                self.qualified.clear();
+            }
+            ServerMessage::RoundResults(results) => {
+                self.round_results = Some(results);
+            }
+            ServerMessage::TrackRecord(record) => {
+                self.track_record = record;
+            }
+            ServerMessage::NewRecord(record) => {
+                self.text2 = Some((
+                    format!(
+                        "New record! {} {:.2}s",
+                        if record.name.is_empty() {
+                            "someone"
+                        } else {
+                            record.name.as_str()
+                        },
+                        record.time,
+                    ),
+                    -2.0,
+                ));
+                if let Some(track_record) = &mut self.track_record {
+                    track_record.record = record;
                 }
-                ServerMessage::Numbers(numbers) => {
-                    self.numbers = numbers;
+            }
+            ServerMessage::Numbers(numbers) => {
+                self.numbers = numbers;
+            }
+            ServerMessage::Rooms(rooms) => {
+                self.rooms = rooms;
+            }
+            ServerMessage::YourId(id) => {
+                self.player_id = Some(id);
+            }
+            ServerMessage::JoinedRoom(room) => {
+                self.room = Some(room);
+                self.player_id = None;
+                self.last_snapshot_time = 0.0;
+                self.snapshots = default();
+                self.track_record = None;
+                self.round_time = None;
+                self.round_results = None;
+                self.qualified.clear();
+                self.remote_players.clear();
+                self.names.clear();
+                self.emotes.clear();
+                self.winner = None;
+                if !self.args.editor && self.practice.is_none() {
+                    self.player = None;
+                    self.spectating = true;
                 }
-                ServerMessage::NewRound(round) => {
-                    if !self.args.editor && self.practice.is_none() {
-                        self.player = None;
-                    }
-                    self.winner = None;
-                    self.cat_move_time = 3.0;
-                    self.remote_players.clear();
+                self.rooms_refresh = 0.0;
+            }
+            ServerMessage::RoomNotFound(room) => {
+                self.text2 = Some((format!("Room {room} not found"), -2.0));
+            }
+            ServerMessage::WrongPassword(room) => {
+                self.text2 = Some((format!("Wrong password for room {room}"), -2.0));
+                // Rooms are picked in the settings
+                if self.in_settings {
+                    self.open_password_prompt(room);
+                }
+            }
+            ServerMessage::ResumeToken(token) => {
+                self.connection.set_resume_token(token);
+            }
+            ServerMessage::Kicked(reason) => {
+                self.text2 = Some((reason, -5.0));
+                self.remote_players.clear();
+                if !self.args.editor && self.practice.is_none() {
+                    self.player = None;
+                    self.spectating = true;
+                }
+            }
+            ServerMessage::Announcement(message) => {
+                self.text2 = Some((message, -5.0));
+            }
+            ServerMessage::Chat(id, text) => {
+                let name = match self.names.get(&id) {
+                    Some(name) => name.clone(),
+                    None if Some(id) == self.player_id => self.name.clone(),
+                    None => "someone".to_owned(),
+                };
+                self.chat.push_back(ChatLine {
+                    name,
+                    text,
+                    age: 0.0,
+                });
+                while self.chat.len() > CHAT_HISTORY {
+                    self.chat.pop_front();
+                }
+            }
+            ServerMessage::Emote(id, emote) => {
+                self.emotes.insert(Some(id), (emote, 0.0));
+            }
+            ServerMessage::YourEmote(emote) => {
+                self.emotes.insert(None, (emote, 0.0));
+            }
+            ServerMessage::ChatRateLimited(wait) => {
+                self.text2 = Some((
+                    format!("Slow down, you can chat again in {:.0}s", wait.ceil()),
+                    -2.0,
+                ));
+            }
+            ServerMessage::RoomRateLimited(wait) => {
+                self.text2 = Some((format!("Slow down, try again in {:.0}s", wait.ceil()), -2.0));
+            }
+            ServerMessage::AdminResponse(response) => {
+                info!("{response}");
+            }
+            ServerMessage::Leaderboard(leaderboard) => {
+                self.leaderboard = leaderboard;
+            }
+            ServerMessage::NewRound(round) => {
+                if !self.args.editor && self.practice.is_none() {
+                    self.player = None;
+                }
+                self.round_time = None;
+                self.winner = None;
+                self.cat_move_time = 3.0;
+                self.remote_players.clear();
+                if self.ready {
+                    self.assets.sfx.countdown.play().set_volume(3.0);
+                }
+                self.round = round;
+                self.text = Some((
+                    if self.round.num == 0 {
+                        "Warmup round!".to_string()
+                    } else {
+                        format!("Round {}!", self.round.num)
+                    } + "\n"
+                        + &self.config.cat_location_text[self.round.track.to],
+                    -2.0,
+                ));
+                self.round_countdown = 3.0;
+            }
+            ServerMessage::PlayerQualified(id, _time) => {
+                self.qualified.push(Some(id));
+            }
+            ServerMessage::YouHaveBeenQualified => {
+                self.qualified.push(None);
+                if !self.args.editor && self.practice.is_none() {
+                    self.player = None;
+                    self.text = Some(("QUALIFIED!!!".to_owned(), 0.0));
                     if self.ready {
-                        self.assets.sfx.countdown.play().set_volume(3.0);
+                        self.assets.sfx.qualified.play();
                     }
-                    self.round = round;
-                    self.text = Some((
-                        if self.round.num == 0 {
-                            "Warmup round!".to_string()
-                        } else {
-                            format!("Round {}!", self.round.num)
-                        } + "\n"
-                            + &self.config.cat_location_text[self.round.track.to],
-                        -2.0,
-                    ));
-                    self.round_countdown = 3.0;
                 }
-                ServerMessage::YouHaveBeenQualified => {
-                    if !self.args.editor && self.practice.is_none() {
-                        self.player = None;
-                        self.text = Some(("QUALIFIED!!!".to_owned(), 0.0));
-                        if self.ready {
-                            self.assets.sfx.qualified.play();
-                        }
-                    }
+            }
+            ServerMessage::YouAreWinner => {
+                if self.ready {
+                    self.assets.sfx.victory.play();
                 }
-                ServerMessage::YouAreWinner => {
+                self.winner = Some(Winner::Me);
+                self.text = Some(("You are the champion!".to_owned(), -2.0));
+            }
+            ServerMessage::Winner(winner) => match winner {
+                Some(id) => {
                     if self.ready {
                         self.assets.sfx.victory.play();
                     }
-                    self.winner = Some(Winner::Me);
-                    self.text = Some(("You are the champion!".to_owned(), -2.0));
+                    self.winner = Some(Winner::Other(id));
+                    self.text = Some(("Champion!".to_owned(), -2.0));
                 }
-                ServerMessage::Winner(winner) => match winner {
-                    Some(id) => {
-                        if self.ready {
-                            self.assets.sfx.victory.play();
-                        }
-                        self.winner = Some(Winner::Other(id));
-                        self.text = Some(("Champion!".to_owned(), -2.0));
-                    }
-                    None => {
-                        if self.ready {
-                            self.assets.sfx.eliminated.play();
-                        }
-                        self.winner = Some(Winner::None);
-                        self.text = Some(("Nobody won :(".to_owned(), -2.0));
+                None => {
+                    if self.ready {
+                        self.assets.sfx.eliminated.play();
                     }
-                },
+                    self.winner = Some(Winner::None);
+                    self.text = Some(("Nobody won :(".to_owned(), -2.0));
+                }
+            },
+        }
+    }
+
+    /// Switch to the level of the server, positions in snapshots depend on it too
+    fn set_level(&mut self, level: Level) {
+        if self.args.editor {
+            return;
+        }
+        self.quantizer = snapshot::Quantizer::new(&level, &self.config);
+        self.navigation = navigation::Navigation::new(&level, &self.config);
+        if let Some(to) = self.practice {
+            if to >= level.cat_locations.len() {
+                self.practice = Some(0);
+            }
+        }
+        self.level = level;
+        self.target_path.clear();
+        self.next_path_update = 0.0;
+    }
+
+    fn apply_snapshot(&mut self, snapshot: Snapshot) {
+        if snapshot.time < self.last_snapshot_time {
+            return;
+        }
+        let time = snapshot.time;
+        let seq = snapshot.seq;
+        let players: Vec<(Id, Player)> = match self.snapshots.decode(snapshot) {
+            Some(frame) => frame
+                .iter()
+                .map(|(&id, packed)| (id, self.quantizer.unpack(packed)))
+                .collect(),
+            None => return,
+        };
+        self.connection.send(ClientMessage::AckSnapshot(seq));
+        self.last_snapshot_time = time;
+        let ids: HashSet<Id> = players.iter().map(|&(id, _)| id).collect();
+        self.remote_players.retain(|id, _| ids.contains(id));
+        for (id, player) in players {
+            match self.remote_players.entry(id) {
+                std::collections::hash_map::Entry::Occupied(mut entry) => {
+                    entry.get_mut().server_update(player);
+                }
+                std::collections::hash_map::Entry::Vacant(entry) => {
+                    entry.insert(RemotePlayer::new(player));
+                }
+            }
+        }
+    }
+
+    fn send_inputs(&mut self) {
+        if !self.connection.is_connected() {
+            return;
+        }
+        let first = self.sent_inputs;
+        let inputs: Vec<PlayerInput> = self
+            .pending_inputs
+            .iter()
+            .filter(|&&(seq, _)| seq >= first)
+            .map(|&(_, input)| input)
+            .collect();
+        if !inputs.is_empty() {
+            self.connection.send(ClientMessage::Input(first, inputs));
+        }
+        self.sent_inputs = self.next_input;
+    }
+
+    fn oldest_pending_input(&self) -> u64 {
+        match self.pending_inputs.front() {
+            Some(&(seq, _)) => seq,
+            None => self.next_input,
+        }
+    }
+
+    fn resend_inputs(&mut self, from: u64) {
+        // A new session counts from the start, so number the unacknowledged inputs from there
+        if self.oldest_pending_input() > from {
+            for (seq, (pending, _)) in (from..).zip(&mut self.pending_inputs) {
+                *pending = seq;
             }
+            self.next_input = from + self.pending_inputs.len() as u64;
+        }
+        self.sent_inputs = from;
+    }
+
+    /// Take acknowledged server state and replay the inputs it has not seen yet
+    fn reconcile(&mut self, ack: u64, server_player: Player) {
+        self.pending_inputs.retain(|&(seq, _)| seq >= ack);
+        if self.args.editor || self.practice.is_some() {
+            return;
+        }
+        if let Some(player) = &mut self.player {
+            let mut new_player = Player {
+                skin: player.skin,
+                color: player.color,
+                ..server_player
+            };
+            for (_, input) in &self.pending_inputs {
+                new_player = physics::step(
+                    &new_player,
+                    input,
+                    &self.level,
+                    &self.config,
+                    physics::DELTA_TIME,
+                )
+                .player;
+            }
+            *player = new_player;
         }
     }
 
@@ -663,6 +1191,178 @@ impl Game {
         );
     }
 
+    fn draw_round_results(
+        &self,
+        framebuffer: &mut ugli::Framebuffer,
+        ui_camera: &geng::Camera2d,
+        ui_aabb: Aabb2<f32>,
+        results: &RoundResults,
+    ) {
+        const MAX_LINES: usize = 10;
+        const LINE_HEIGHT: f32 = 0.45;
+        let name = |id: Id, name: &str| -> String {
+            if !name.is_empty() {
+                return name.to_owned();
+            }
+            match self.names.get(&id) {
+                Some(name) if !name.is_empty() => name.clone(),
+                _ => "bot".to_owned(),
+            }
+        };
+        let mut lines = vec![(
+            if results.round == 0 {
+                "Warmup results".to_owned()
+            } else {
+                format!("Round {} results", results.round)
+            },
+            Rgba::WHITE,
+        )];
+        for (place, (id, player_name, time)) in results.finished.iter().enumerate() {
+            let player_name = name(*id, player_name);
+            let color = if player_name == self.name {
+                Rgba::YELLOW
+            } else {
+                Rgba::WHITE
+            };
+            lines.push((format!("{}. {player_name} {time:.2}s", place + 1), color));
+        }
+        for (id, player_name) in &results.eliminated {
+            lines.push((
+                format!("x {}", name(*id, player_name)),
+                Rgba::new(0.8, 0.8, 0.8, 1.0),
+            ));
+        }
+        let hidden = lines.len().saturating_sub(MAX_LINES);
+        lines.truncate(MAX_LINES);
+        if hidden > 0 {
+            lines.push((format!("and {hidden} more"), Rgba::new(0.8, 0.8, 0.8, 1.0)));
+        }
+        for (index, (text, color)) in lines.iter().enumerate() {
+            self.assets.font.draw_with_outline(
+                framebuffer,
+                ui_camera,
+                text,
+                vec2(
+                    ui_aabb.min.x + 0.5,
+                    ui_aabb.max.y - 1.0 - index as f32 * LINE_HEIGHT,
+                ),
+                geng::TextAlign::LEFT,
+                0.4,
+                *color,
+                0.05,
+                Rgba::BLACK,
+            );
+        }
+    }
+
+    /// Qualified players first, then everyone racing by path distance to Coots
+    fn draw_standings(
+        &self,
+        framebuffer: &mut ugli::Framebuffer,
+        ui_camera: &geng::Camera2d,
+        ui_aabb: Aabb2<f32>,
+    ) {
+        const MAX_LINES: usize = 8;
+        const LINE_HEIGHT: f32 = 0.45;
+        let cat = self.round.track.to;
+        let mut racing: Vec<(Option<Id>, f32)> = self
+            .remote_players
+            .iter()
+            .filter(|(id, _)| !self.qualified.contains(&Some(**id)))
+            .map(|(&id, player)| {
+                (
+                    Some(id),
+                    self.navigation.distance_to_cat(cat, player.get().pos),
+                )
+            })
+            .collect();
+        if let Some(player) = &self.player {
+            if !self.qualified.contains(&None) {
+                racing.push((None, self.navigation.distance_to_cat(cat, player.pos)));
+            }
+        }
+        racing.sort_by_key(|&(_, distance)| r32(distance.min(1e9)));
+        let standings: Vec<(Option<Id>, Option<f32>)> = self
+            .qualified
+            .iter()
+            .map(|&id| (id, None))
+            .chain(
+                racing
+                    .into_iter()
+                    .map(|(id, distance)| (id, Some(distance))),
+            )
+            .collect();
+        let my_place = standings.iter().position(|&(id, _)| id.is_none());
+        for (place, &(id, distance)) in standings.iter().enumerate() {
+            let line = if place < MAX_LINES {
+                place
+            } else if Some(place) == my_place {
+                MAX_LINES
+            } else {
+                continue;
+            };
+            let name = match id {
+                None => self.name.as_str(),
+                Some(id) => match self.names.get(&id) {
+                    Some(name) if !name.is_empty() => name.as_str(),
+                    _ => "bot",
+                },
+            };
+            let text = match distance {
+                None => format!("{}. {name} done", place + 1),
+                Some(distance) if distance.is_finite() => {
+                    format!("{}. {name} {distance:.0}m", place + 1)
+                }
+                Some(_) => format!("{}. {name}", place + 1),
+            };
+            self.assets.font.draw_with_outline(
+                framebuffer,
+                ui_camera,
+                &text,
+                vec2(
+                    ui_aabb.max.x - 0.5,
+                    ui_aabb.max.y - 1.0 - line as f32 * LINE_HEIGHT,
+                ),
+                geng::TextAlign::RIGHT,
+                0.4,
+                if id.is_none() {
+                    Rgba::YELLOW
+                } else {
+                    Rgba::WHITE
+                },
+                0.05,
+                Rgba::BLACK,
+            );
+        }
+    }
+
+    /// Where the record run was at this point of the round
+    fn ghost(&self) -> Option<Player> {
+        let time = self.round_time?;
+        let ghost = &self.track_record.as_ref()?.ghost;
+        let index = ghost.partition_point(|frame| frame.time <= time);
+        let next = ghost.get(index)?;
+        let prev = &ghost[index.max(1) - 1];
+        let t = ((time - prev.time) / (next.time - prev.time).max(1e-5)).clamp(0.0, 1.0);
+        Some(Player {
+            color: 0.0,
+            skin: 0,
+            pos: prev.pos * (1.0 - t) + next.pos * t,
+            vel: vec2::ZERO,
+            rot: prev.rot * (1.0 - t) + next.rot * t,
+        })
+    }
+
+    fn draw_ghost_car(&self, ghost: &Player) {
+        self.add_texture_instance(
+            &self.assets.car,
+            Rgba::new(1.0, 1.0, 1.0, GHOST_ALPHA),
+            mat3::translate(ghost.pos + vec2(0.0, 0.4))
+                * mat3::scale(self.config.player_direction_scale * self.config.player_radius * 0.7)
+                * mat3::rotate(ghost.rot),
+        );
+    }
+
     fn draw_player_body(
         &self,
         framebuffer: &mut ugli::Framebuffer,
@@ -776,6 +1476,160 @@ impl Game {
                 Rgba::BLACK,
             );
         }
+        if let Some(&(emote, age)) = self.emotes.get(&id) {
+            if let Some(text) = self.config.emotes.get(emote) {
+                // Pops in and fades out
+                let size = EMOTE_SIZE * (age / EMOTE_POP_TIME).min(1.0);
+                let alpha = ((EMOTE_TIME - age) / EMOTE_FADE_TIME).clamp(0.0, 1.0);
+                let pos = player.pos
+                    + vec2(
+                        0.0,
+                        self.config.player_radius * 2.0 + self.config.nameplate_size * 1.5,
+                    );
+                self.geng.draw_2d(
+                    framebuffer,
+                    camera,
+                    &draw_2d::Ellipse::new(
+                        pos + vec2(0.0, size * 0.4),
+                        vec2(text.len() as f32 * 0.3 + 0.5, 0.8) * size,
+                        Rgba::new(1.0, 1.0, 1.0, 0.8 * alpha),
+                    ),
+                );
+                self.assets.font.draw_with_outline(
+                    framebuffer,
+                    camera,
+                    text,
+                    pos,
+                    geng::TextAlign::CENTER,
+                    size,
+                    Rgba::new(0.0, 0.0, 0.0, alpha),
+                    self.config.nameplate_outline_size,
+                    Rgba::new(1.0, 1.0, 1.0, alpha),
+                );
+            }
+        }
+    }
+
+    fn send_emote(&mut self, emote: usize) {
+        if emote < self.config.emotes.len() {
+            self.connection.send(ClientMessage::Emote(emote));
+        }
+    }
+
+    /// Text itself comes from the window so it follows the keyboard layout
+    fn open_chat(&mut self) {
+        self.chat_input = Some(String::new());
+        self.geng.window().start_text_edit("");
+    }
+
+    fn open_password_prompt(&mut self, room: RoomId) {
+        self.password_prompt = Some((room, String::new()));
+        self.geng.window().start_text_edit("");
+    }
+
+    fn password_key(&mut self, key: geng::Key) {
+        match key {
+            geng::Key::Enter => {
+                if let Some((id, password)) = self.password_prompt.take() {
+                    self.connection.send(ClientMessage::JoinRoom {
+                        id,
+                        password: Some(password),
+                    });
+                }
+                self.geng.window().stop_text_edit();
+            }
+            geng::Key::Escape => {
+                self.password_prompt = None;
+                self.geng.window().stop_text_edit();
+            }
+            _ => {}
+        }
+    }
+
+    fn chat_key(&mut self, key: geng::Key) {
+        match key {
+            geng::Key::Enter => {
+                if let Some(text) = self.chat_input.take() {
+                    let text = text.trim().to_owned();
+                    if !text.is_empty() {
+                        self.connection.send(ClientMessage::Chat(text));
+                    }
+                }
+                self.geng.window().stop_text_edit();
+            }
+            geng::Key::Escape => {
+                self.chat_input = None;
+                self.geng.window().stop_text_edit();
+            }
+            _ => {}
+        }
+    }
+
+    fn player_input(&mut self) -> PlayerInput {
+        while let Some(event) = self.gilrs.next_event() {
+            self.active_gamepad = Some(event.id);
+            // D-pad sends the first four emotes
+            if let gilrs::EventType::ButtonPressed(button, _) = event.event {
+                match button {
+                    gilrs::Button::DPadUp => self.send_emote(0),
+                    gilrs::Button::DPadRight => self.send_emote(1),
+                    gilrs::Button::DPadDown => self.send_emote(2),
+                    gilrs::Button::DPadLeft => self.send_emote(3),
+                    _ => {}
+                }
+            }
+        }
+        // Keys go to the chat while typing
+        let pressed =
+            |key: geng::Key| self.chat_input.is_none() && self.geng.window().is_key_pressed(key);
+
+        PlayerInput {
+            rotate: {
+                let mut value: f32 = 0.0;
+                if pressed(geng::Key::Left) || pressed(geng::Key::A) {
+                    value += 1.0;
+                }
+                if pressed(geng::Key::Right) || pressed(geng::Key::D) {
+                    value -= 1.0;
+                }
+                if let Some(gamepad) = self
+                    .active_gamepad
+                    .and_then(|id| self.gilrs.connected_gamepad(id))
+                {
+                    if let Some(axis) = gamepad.axis_data(gilrs::Axis::LeftStickX) {
+                        value -= axis.value();
+                    }
+                }
+                value.clamp(-1.0, 1.0)
+            },
+            accelerate: {
+                let mut value: f32 = 0.0;
+                if pressed(geng::Key::Down) || pressed(geng::Key::S) {
+                    value -= 1.0;
+                }
+                if pressed(geng::Key::Up) || pressed(geng::Key::W) {
+                    value += 1.0;
+                }
+                if let Some(gamepad) = self
+                    .active_gamepad
+                    .and_then(|id| self.gilrs.connected_gamepad(id))
+                {
+                    if let Some(button) = gamepad.button_data(gilrs::Button::LeftTrigger) {
+                        value -= button.value();
+                    }
+                    if let Some(button) = gamepad.button_data(gilrs::Button::LeftTrigger2) {
+                        value -= button.value();
+                    }
+                    if let Some(button) = gamepad.button_data(gilrs::Button::RightTrigger) {
+                        value += button.value();
+                    }
+                    if let Some(button) = gamepad.button_data(gilrs::Button::RightTrigger2) {
+                        value += button.value();
+                    }
+                }
+                value.clamp(-1.0, 1.0)
+            },
+        }
     }
 
     fn update_my_player(&mut self, delta_time: f32) {
@@ -869,119 +1723,27 @@ impl Game {
         self.camera.fov +=
             (target_fov - self.camera.fov) * (self.config.zoom_speed * delta_time).min(1.0);
 
-        let player = match &mut self.player {
-            Some(player) => player,
-            None => {
-                self.drift_sfx.set_volume(0.0);
-                self.forward_sfx.set_volume(0.0);
-                return;
-            }
-        };
+        if self.player.is_none() {
+            self.drift_sfx.set_volume(0.0);
+            self.forward_sfx.set_volume(0.0);
+            return;
+        }
 
         self.next_player_update -= delta_time;
         while self.next_player_update < 0.0 {
-            let delta_time = 1.0 / 200.0;
+            let delta_time = physics::DELTA_TIME;
             self.next_player_update += delta_time;
 
-            while let Some(event) = self.gilrs.next_event() {
-                self.active_gamepad = Some(event.id);
+            let input = self.player_input();
+            if self.config.server_authoritative && self.practice.is_none() && !self.args.editor {
+                self.pending_inputs.push_back((self.next_input, input));
+                self.next_input += 1;
             }
+            let player = self.player.as_mut().unwrap();
+            let step = physics::step(player, &input, &self.level, &self.config, delta_time);
+            *player = step.player;
 
-            let input = PlayerInput {
-                rotate: {
-                    let mut value: f32 = 0.0;
-                    if self.geng.window().is_key_pressed(geng::Key::Left)
-                        || self.geng.window().is_key_pressed(geng::Key::A)
-                    {
-                        value += 1.0;
-                    }
-                    if self.geng.window().is_key_pressed(geng::Key::Right)
-                        || self.geng.window().is_key_pressed(geng::Key::D)
-                    {
-                        value -= 1.0;
-                    }
-                    if let Some(gamepad) = self
-                        .active_gamepad
-                        .and_then(|id| self.gilrs.connected_gamepad(id))
-                    {
-                        if let Some(axis) = gamepad.axis_data(gilrs::Axis::LeftStickX) {
-                            value -= axis.value();
-                        }
-                    }
-                    value.clamp(-1.0, 1.0)
-                },
-                accelerate: {
-                    let mut value: f32 = 0.0;
-                    if self.geng.window().is_key_pressed(geng::Key::Down)
-                        || self.geng.window().is_key_pressed(geng::Key::S)
-                    {
-                        value -= 1.0;
-                    }
-                    if self.geng.window().is_key_pressed(geng::Key::Up)
-                        || self.geng.window().is_key_pressed(geng::Key::W)
-                    {
-                        value += 1.0;
-                    }
-                    if let Some(gamepad) = self
-                        .active_gamepad
-                        .and_then(|id| self.gilrs.connected_gamepad(id))
-                    {
-                        if let Some(button) = gamepad.button_data(gilrs::Button::LeftTrigger) {
-                            value -= button.value();
-                        }
-                        if let Some(button) = gamepad.button_data(gilrs::Button::LeftTrigger2) {
-                            value -= button.value();
-                        }
-                        if let Some(button) = gamepad.button_data(gilrs::Button::RightTrigger) {
-                            value += button.value();
-                        }
-                        if let Some(button) = gamepad.button_data(gilrs::Button::RightTrigger2) {
-                            value += button.value();
-                        }
-                    }
-                    value.clamp(-1.0, 1.0)
-                },
-            };
-
-            player.rot += input.rotate * self.config.rotation_speed * delta_time;
-            let dir = vec2(1.0, 0.0).rotate(player.rot);
-
-            let mut forward_vel = vec2::dot(dir, player.vel);
-            let (target_forward_vel, forward_acceleration) = if input.accelerate > 0.0 {
-                let target_forward_vel = input.accelerate * self.config.max_speed;
-                let forward_acceleration = if target_forward_vel > forward_vel {
-                    if forward_vel < 0.0 {
-                        self.config.deceleration
-                    } else {
-                        self.config.acceleration
-                    }
-                } else {
-                    -self.config.deceleration
-                };
-                (target_forward_vel, forward_acceleration)
-            } else {
-                let target_forward_vel = input.accelerate * self.config.max_backward_speed;
-                let forward_acceleration = if target_forward_vel < forward_vel {
-                    if forward_vel > 0.0 {
-                        -self.config.deceleration
-                    } else {
-                        -self.config.backward_acceleration
-                    }
-                } else {
-                    self.config.deceleration
-                };
-                (target_forward_vel, forward_acceleration)
-            };
-            forward_vel += (target_forward_vel - forward_vel)
-                .clamp_abs(forward_acceleration.abs() * delta_time);
-
-            let mut drift_vel = vec2::skew(dir, player.vel);
-            drift_vel -= drift_vel.clamp_abs(self.config.drift_deceleration * delta_time);
-
-            let old_vel = player.vel;
-            player.vel = dir * forward_vel + dir.rotate_90() * drift_vel;
-
-            let drift_value = drift_vel.abs();
+            let drift_value = step.drift_vel.abs();
             self.drift_sfx
                 .set_volume(self.config.drift_sfx.get(drift_value));
             self.drift_sfx.set_speed(
@@ -994,7 +1756,7 @@ impl Game {
                 self.next_drift_particle += 1.0 / self.config.drift_particles;
                 self.drift_particles.push(player.pos, player.vel);
             }
-            let forward_value = forward_vel.abs();
+            let forward_value = step.forward_vel.abs();
             self.forward_sfx
                 .set_volume(self.config.forward_sfx.get(forward_value));
             self.forward_sfx.set_speed(
@@ -1003,35 +1765,8 @@ impl Game {
                     + 1.0,
             );
 
-            player.pos += player.vel * delta_time;
-            #[derive(PartialEq)]
-            struct Collision {
-                n: vec2<f32>,
-                penetration: f32,
-            }
-            impl PartialOrd for Collision {
-                fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-                    Some(
-                        self.penetration
-                            .partial_cmp(&other.penetration)
-                            .unwrap()
-                            .reverse(),
-                    )
-                }
-            }
-            let mut collision = None;
-            for &[p1, p2] in &self.level.segments {
-                let v = -vector_from(player.pos, p1, p2);
-                let penetration = self.config.player_radius - v.len();
-                let n = v.normalize_or_zero();
-                if penetration > 0.0 {
-                    collision = partial_max(collision, Some(Collision { n, penetration }));
-                }
-            }
-            if let Some(Collision { n, penetration }) = collision {
-                player.pos += n * penetration;
-                let v = vec2::dot(n, player.vel);
-                let sfx_volume = self.config.bounce_sfx.get(v.abs());
+            if let Some(collision) = step.collision {
+                let sfx_volume = self.config.bounce_sfx.get(collision.impact.abs());
                 if sfx_volume > 0.0 {
                     let mut effect = self.assets.sfx.bounce.effect();
                     effect.set_speed(thread_rng().gen_range(0.8..1.2));
@@ -1039,15 +1774,18 @@ impl Game {
                     effect.play();
                     for _ in 0..(sfx_volume * 2.0) as usize {
                         self.bounce_particles.push(
-                            player.pos - n * self.config.player_radius,
-                            -player.vel * 0.5,
+                            player.pos - collision.normal * self.config.player_radius,
+                            -collision.vel * 0.5,
                         );
                     }
                 }
-                player.vel -= n * v * (1.0 + self.config.collision_bounciness);
             }
         }
+        if self.config.server_authoritative && self.practice.is_none() && !self.args.editor {
+            self.send_inputs();
+        }
 
+        let player = self.player.as_ref().unwrap();
         let cat_pos = self.level.cat_locations[self.round.track.to];
         if (player.pos - cat_pos).len() < self.config.player_radius * 2.0 && self.text.is_none() {
             self.text = Some(("STOP!".to_owned(), 0.0));
@@ -1070,6 +1808,53 @@ impl Game {
             * (self.config.camera_speed * delta_time).min(1.0);
     }
 
+    /// Cat location the player is racing to
+    fn target(&self) -> Option<vec2<f32>> {
+        self.level
+            .cat_locations
+            .get(self.practice.unwrap_or(self.round.track.to))
+            .copied()
+    }
+
+    fn update_target_path(&mut self) {
+        self.target_path = match (&self.player, self.target()) {
+            (Some(player), Some(target)) if self.path_arrow || self.breadcrumbs => self
+                .navigation
+                .path(player.pos, target)
+                .unwrap_or_default(),
+            _ => vec![],
+        };
+    }
+
+    /// Dots on the floor moving along the path to Coots
+    fn draw_breadcrumbs(&self, framebuffer: &mut ugli::Framebuffer, camera: &geng::Camera2d) {
+        let player = match &self.player {
+            Some(player) => player,
+            None => return,
+        };
+        let color = {
+            let mut c: Rgba<f32> = Hsva::new(self.color, 1.0, 1.0, 1.0).into();
+            c.a = BREADCRUMB_ALPHA;
+            c
+        };
+        let mut from = player.pos;
+        let mut offset = (self.t * BREADCRUMB_SPEED).rem_euclid(BREADCRUMB_SPACING);
+        for &to in self.target_path.iter().skip(1) {
+            let len = (to - from).len();
+            let dir = (to - from).normalize_or_zero();
+            while offset < len {
+                self.geng.draw_2d(
+                    framebuffer,
+                    camera,
+                    &draw_2d::Ellipse::circle(from + dir * offset, BREADCRUMB_RADIUS, color),
+                );
+                offset += BREADCRUMB_SPACING;
+            }
+            offset -= len;
+            from = to;
+        }
+    }
+
     fn draw_game(&mut self, framebuffer: &mut ugli::Framebuffer) {
         ugli::clear(framebuffer, Some(Rgba::BLACK), None, None);
 
@@ -1137,7 +1922,14 @@ impl Game {
             error!("Cat location not found!");
         }
 
+        if self.breadcrumbs {
+            self.draw_breadcrumbs(framebuffer, camera);
+        }
+
         if self.practice.is_none() {
+            if let Some(ghost) = self.ghost() {
+                self.draw_ghost_car(&ghost);
+            }
             for (&id, player) in &self.remote_players {
                 self.draw_player_car(framebuffer, camera, &player.get(), Some(id));
             }
@@ -1245,6 +2037,17 @@ impl Game {
             .get(self.practice.unwrap_or(self.round.track.to))
         {
             if !camera_aabb.contains(pos) {
+                // Where the path to Coots leaves the screen
+                let pos = match self.path_arrow {
+                    true => self
+                        .target_path
+                        .iter()
+                        .skip(1)
+                        .copied()
+                        .find(|&pos| !camera_aabb.contains(pos))
+                        .unwrap_or(pos),
+                    false => pos,
+                };
                 let mut aabb = camera_aabb.extend_uniform(-self.config.arrow_size);
                 if aabb.max.x < aabb.min.x {
                     aabb.max.x = aabb.min.x;
@@ -1306,6 +2109,8 @@ impl Game {
             ui_camera,
             &if let Some(to) = self.practice {
                 format!("SOLO PRACTICE MODE\n{}", self.config.cat_location_text[to])
+            } else if let Some(replay) = &self.replay {
+                self.replay_status(replay)
             } else if self.player.is_some() {
                 self.config.cat_location_text[self.round.track.to].clone()
             } else if self.spectating {
@@ -1327,6 +2132,42 @@ impl Game {
             0.05,
             Rgba::BLACK,
         );
+        if self.practice.is_none() {
+            if let Some(results) = &self.round_results {
+                self.draw_round_results(framebuffer, ui_camera, ui_aabb, results);
+            }
+        }
+        if self.practice.is_none() && self.round_time.is_some() {
+            self.draw_standings(framebuffer, ui_camera, ui_aabb);
+        }
+        if self.practice.is_none() && self.round_time.is_some() {
+            if let Some(track_record) = &self.track_record {
+                self.assets.font.draw_with_outline(
+                    framebuffer,
+                    ui_camera,
+                    &format_record(&track_record.record),
+                    vec2(0.0, -4.7),
+                    geng::TextAlign::CENTER,
+                    0.4,
+                    Rgba::WHITE,
+                    0.05,
+                    Rgba::BLACK,
+                );
+            }
+        }
+        if let Some(error) = &self.error {
+            self.assets.font.draw_with_outline(
+                framebuffer,
+                ui_camera,
+                error,
+                vec2(0.0, 1.0),
+                geng::TextAlign::CENTER,
+                0.7,
+                Rgba::RED,
+                0.05,
+                Rgba::BLACK,
+            );
+        }
         if let Some(cat) = self.practice {
             let cat = self.level.cat_locations[cat];
             if let Some(player) = &self.player {
@@ -1747,7 +2588,11 @@ impl Game {
 
 impl geng::State for Game {
     fn update(&mut self, delta_time: f64) {
-        let delta_time = delta_time as f32;
+        let real_delta_time = delta_time as f32;
+        let delta_time = match &mut self.replay {
+            Some(replay) => replay.playback.advance(real_delta_time),
+            None => real_delta_time,
+        };
 
         self.round_countdown -= delta_time;
 
@@ -1782,13 +2627,37 @@ impl geng::State for Game {
         }
 
         self.cat_move_time -= delta_time;
+        if let Some(time) = &mut self.round_time {
+            *time += delta_time;
+        }
+
+        self.rooms_refresh -= delta_time;
+        if self.in_settings && self.rooms_refresh < 0.0 {
+            self.rooms_refresh = ROOMS_REFRESH_INTERVAL;
+            self.connection.send(ClientMessage::ListRooms);
+            self.connection.send(ClientMessage::Leaderboard);
+        }
 
-        self.update_connection();
+        if self.replay.is_some() {
+            self.update_replay();
+        } else {
+            self.update_connection(delta_time);
+        }
         for player in self.remote_players.values_mut() {
             player.update(delta_time);
         }
 
-        self.update_my_player(delta_time);
+        if self.replay.is_some() {
+            self.update_replay_camera(real_delta_time);
+        } else {
+            self.update_my_player(delta_time);
+        }
+
+        self.next_path_update -= delta_time;
+        if self.next_path_update < 0.0 {
+            self.next_path_update = PATH_UPDATE_INTERVAL;
+            self.update_target_path();
+        }
 
         if let Some((_text, time)) = &mut self.text {
             *time += delta_time;
@@ -1802,6 +2671,13 @@ impl geng::State for Game {
                 self.text2 = None;
             }
         }
+        for line in &mut self.chat {
+            line.age += delta_time;
+        }
+        for (_, age) in self.emotes.values_mut() {
+            *age += delta_time;
+        }
+        self.emotes.retain(|_, &mut (_, age)| age < EMOTE_TIME);
     }
     fn draw(&mut self, framebuffer: &mut ugli::Framebuffer) {
         self.framebuffer_size = framebuffer.size().map(|x| x as f32);
@@ -1835,6 +2711,36 @@ impl geng::State for Game {
 
     fn handle_event(&mut self, event: geng::Event) {
         match event {
+            geng::Event::EditText(text) if self.in_settings && self.password_prompt.is_some() => {
+                if let Some((_, password)) = &mut self.password_prompt {
+                    *password = text;
+                }
+            }
+            geng::Event::KeyDown { key } if self.in_settings && self.password_prompt.is_some() => {
+                self.password_key(key);
+            }
+            geng::Event::EditText(text) if self.chat_input.is_some() => {
+                self.chat_input = Some(text.chars().take(self.config.max_chat_len).collect());
+            }
+            geng::Event::KeyDown { key } if self.chat_input.is_some() => {
+                self.chat_key(key);
+            }
+            geng::Event::KeyDown {
+                key: geng::Key::Enter,
+            } if !self.in_settings && self.replay.is_none() => {
+                self.open_chat();
+            }
+            geng::Event::KeyDown { key }
+                if !self.in_settings && self.replay.is_none() && emote_key(key).is_some() =>
+            {
+                self.send_emote(emote_key(key).unwrap());
+            }
+            geng::Event::Wheel { delta } if self.replay.is_some() => {
+                if let Some(replay) = &mut self.replay {
+                    replay.fov = (replay.fov * if delta > 0.0 { 0.8 } else { 1.25 })
+                        .clamp(self.config.camera_fov * 0.5, self.config.map_scale * 2.0);
+                }
+            }
             geng::Event::Wheel { delta } => {
                 if self.spectating {
                     self.spectate_zoomed_in = delta > 0.0;
@@ -1910,6 +2816,9 @@ impl geng::State for Game {
             geng::Event::KeyDown { key: geng::Key::M } if !self.in_settings => {
                 self.music_on = !self.music_on; // TODO ui
             }
+            geng::Event::KeyDown { key } if self.replay.is_some() && !self.in_settings => {
+                self.handle_replay_key(key);
+            }
             geng::Event::KeyDown { key } if self.in_settings => {
                 let old_name = self.name.clone();
                 if key == geng::Key::Backspace {
@@ -2014,11 +2923,20 @@ impl geng::State for Game {
                 &self.assets.ui.sfx,
                 1.0,
             );
+            if play_button.was_clicked() || practice_button.was_clicked() {
+                if self.password_prompt.take().is_some() {
+                    self.geng.window().stop_text_edit();
+                }
+            }
             if play_button.was_clicked() {
                 self.in_settings = false;
                 self.ready = true;
                 self.practice = None;
                 self.connection.send(ClientMessage::Name(self.name.clone()));
+                self.connection.send(ClientMessage::Appearance {
+                    skin: self.skin,
+                    color: self.color,
+                });
                 self.connection.send(ClientMessage::Ready(true));
             }
             if practice_button.was_clicked() {
@@ -2040,6 +2958,121 @@ impl geng::State for Game {
             let play_button = play_button
                 .fixed_size(vec2(2.0, 1.0) * 1.5)
                 .padding_top(-padding);
+
+            let create_room_button =
+                TextButton::new(cx, "new room", &self.assets.font, &self.assets.ui.sfx, 0.7);
+            if create_room_button.was_clicked() {
+                self.connection.send(ClientMessage::CreateRoom {
+                    private: false,
+                    password: None,
+                });
+            }
+            let create_private_room_button = TextButton::new(
+                cx,
+                "new private room",
+                &self.assets.font,
+                &self.assets.ui.sfx,
+                0.7,
+            );
+            if create_private_room_button.was_clicked() {
+                self.connection.send(ClientMessage::CreateRoom {
+                    private: true,
+                    password: self.args.room_password.clone(),
+                });
+            }
+            let mut room_widgets: Vec<Box<dyn Widget + 'a>> = vec![
+                CustomText::new(
+                    format!("room: {}", self.room.as_deref().unwrap_or("...")),
+                    &self.assets.font,
+                    0.7,
+                    Rgba::WHITE,
+                )
+                .center()
+                .boxed(),
+                create_room_button.padding_left(padding).center().boxed(),
+                create_private_room_button
+                    .padding_left(padding)
+                    .center()
+                    .boxed(),
+            ];
+            for room in self
+                .rooms
+                .iter()
+                .filter(|room| Some(&room.id) != self.room.as_ref())
+                .take(5)
+            {
+                let join_button = TextButton::new(
+                    cx,
+                    format!(
+                        "{}{} ({})",
+                        room.id,
+                        if room.password { "*" } else { "" },
+                        room.clients,
+                    ),
+                    &self.assets.font,
+                    &self.assets.ui.sfx,
+                    0.7,
+                );
+                if join_button.was_clicked() {
+                    if room.password && self.args.room_password.is_none() {
+                        self.password_prompt = Some((room.id.clone(), String::new()));
+                        self.geng.window().start_text_edit("");
+                    } else {
+                        self.connection.send(ClientMessage::JoinRoom {
+                            id: room.id.clone(),
+                            password: self.args.room_password.clone(),
+                        });
+                    }
+                }
+                room_widgets.push(join_button.padding_left(padding).center().boxed());
+            }
+            if let Some((id, password)) = &self.password_prompt {
+                room_widgets.push(
+                    CustomText::new(
+                        format!(
+                            "password for {id}: {}_ (enter to join)",
+                            "*".repeat(password.chars().count())
+                        ),
+                        &self.assets.font,
+                        0.7,
+                        Rgba::WHITE,
+                    )
+                    .padding_left(padding)
+                    .center()
+                    .boxed(),
+                );
+            }
+            let rooms = row(room_widgets);
+
+            let mut leaderboard_widgets: Vec<Box<dyn Widget + 'a>> =
+                vec![
+                    CustomText::new("leaderboard", &self.assets.font, 0.7, Rgba::WHITE)
+                        .center()
+                        .boxed(),
+                ];
+            for (place, (name, stats)) in self.leaderboard.iter().enumerate() {
+                leaderboard_widgets.push(
+                    CustomText::new(
+                        format!(
+                            "{}. {name} {}W {}R {}Q",
+                            place + 1,
+                            stats.wins,
+                            stats.rounds_survived,
+                            stats.qualifications,
+                        ),
+                        &self.assets.font,
+                        0.5,
+                        if *name == self.name {
+                            Rgba::YELLOW
+                        } else {
+                            gray
+                        },
+                    )
+                    .center()
+                    .boxed(),
+                );
+            }
+            let leaderboard = column(leaderboard_widgets);
             let practice_button = practice_button
                 .fixed_size(vec2(2.0, 1.0) * 1.5)
                 .padding_top(-padding);
@@ -2060,6 +3093,36 @@ impl geng::State for Game {
                 player.skin = self.skin;
                 player.color = self.color;
             }
+            let path_arrow_button = TextButton::new(
+                cx,
+                if self.path_arrow {
+                    "arrow: follow path"
+                } else {
+                    "arrow: straight"
+                },
+                &self.assets.font,
+                &self.assets.ui.sfx,
+                0.5,
+            );
+            if path_arrow_button.was_clicked() {
+                self.path_arrow = !self.path_arrow;
+                preferences::save("path_arrow", &self.path_arrow);
+            }
+            let breadcrumbs_button = TextButton::new(
+                cx,
+                if self.breadcrumbs {
+                    "trail: on"
+                } else {
+                    "trail: off"
+                },
+                &self.assets.font,
+                &self.assets.ui.sfx,
+                0.5,
+            );
+            if breadcrumbs_button.was_clicked() {
+                self.breadcrumbs = !self.breadcrumbs;
+                preferences::save("breadcrumbs", &self.breadcrumbs);
+            }
             let current_skin = stack![
                 CarWidget::new(
                     &self.assets.car,
@@ -2131,6 +3194,13 @@ impl geng::State for Game {
                     .row()
                     .padding_top(padding)
                     .center(),
+                (
+                    path_arrow_button.center(),
+                    breadcrumbs_button.padding_left(padding).center(),
+                )
+                    .row()
+                    .padding_top(padding)
+                    .center(),
             )
                 .column();
             let volume_settings = (
@@ -2176,6 +3246,7 @@ impl geng::State for Game {
                 (play_button.center(), practice_button.center())
                     .row()
                     .center(),
+                rooms.center(),
                 (
                     TextureWidget::new(&self.assets.ui.background, 1.0),
                     (
@@ -2196,11 +3267,88 @@ impl geng::State for Game {
             )
                 .column()
                 .center();
-            settings.boxed()
-        } else if self.spectating {
-            stack![settings_button, telecam_checkbox].boxed()
+            (settings, leaderboard.padding_left(padding).center())
+                .row()
+                .center()
+                .boxed()
         } else {
-            settings_button.boxed()
+            let typing = self.chat_input.is_some();
+            let mut chat_widgets: Vec<Box<dyn Widget + 'a>> = vec![];
+            let mut toggle_mute = None;
+            for line in &self.chat {
+                if self.muted.contains(&line.name.to_lowercase())
+                    || (!typing && line.age > CHAT_LINE_TIME)
+                {
+                    continue;
+                }
+                let name = format!("{}:", line.name);
+                let text = CustomText::new(
+                    line.text.clone(),
+                    &self.assets.font,
+                    CHAT_TEXT_SIZE,
+                    Rgba::WHITE,
+                )
+                .padding_left(CHAT_TEXT_SIZE as f64 * 0.5)
+                .center();
+                if typing {
+                    // Click a name to mute them
+                    let button = TextButton::new(
+                        cx,
+                        name,
+                        &self.assets.font,
+                        &self.assets.ui.sfx,
+                        CHAT_TEXT_SIZE,
+                    );
+                    if button.was_clicked() {
+                        toggle_mute = Some(line.name.to_lowercase());
+                    }
+                    chat_widgets.push((button.center(), text).row().boxed());
+                } else {
+                    let name =
+                        CustomText::new(name, &self.assets.font, CHAT_TEXT_SIZE, Rgba::YELLOW);
+                    chat_widgets.push((name.center(), text).row().boxed());
+                }
+            }
+            if let Some(input) = &self.chat_input {
+                chat_widgets.push(
+                    CustomText::new(
+                        format!("> {input}_"),
+                        &self.assets.font,
+                        CHAT_TEXT_SIZE,
+                        Rgba::WHITE,
+                    )
+                    .boxed(),
+                );
+                let mut muted: Vec<&String> = self.muted.iter().collect();
+                muted.sort();
+                for name in muted {
+                    let button = TextButton::new(
+                        cx,
+                        format!("unmute {name}"),
+                        &self.assets.font,
+                        &self.assets.ui.sfx,
+                        CHAT_TEXT_SIZE,
+                    );
+                    if button.was_clicked() {
+                        toggle_mute = Some(name.clone());
+                    }
+                    chat_widgets.push(button.boxed());
+                }
+            }
+            if let Some(name) = toggle_mute {
+                if !self.muted.remove(&name) {
+                    self.muted.insert(name);
+                }
+                preferences::save("muted", &self.muted);
+            }
+            let chat = column(chat_widgets)
+                .uniform_padding(padding)
+                .align(vec2(0.0, 0.5));
+            if self.spectating {
+                stack![settings_button, telecam_checkbox, chat].boxed()
+            } else {
+                stack![settings_button, chat].boxed()
+            }
         }
     }
 }
@@ -2,6 +2,11 @@ use super::*;
 
 pub type Id = i64;
 
+pub type RoomId = String;
+
+/// Bump on any change to the messages or [Level] layout
+pub const PROTOCOL_VERSION: u32 = 2;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Player {
     pub color: f32,
@@ -11,15 +16,86 @@ pub struct Player {
     pub rot: f32,
 }
 
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct PlayerInput {
+    pub rotate: f32,     // -1 .. 1
+    pub accelerate: f32, // -1 .. 1
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum ClientMessage {
     Ping,
     UpdatePlayer(Player),
+    Input(u64, Vec<PlayerInput>),
+    AckSnapshot(u64),
+    Resume(String),
+    Admin {
+        password: String,
+        command: String,
+    },
+    Leaderboard,
+    Appearance {
+        skin: usize,
+        color: f32,
+    },
     Name(String),
+    Chat(String),
+    /// Index into [Config::emotes]
+    Emote(usize),
+    /// Sent when the level checksum from [ServerMessage::Hello] differs from the local one
+    DownloadLevel,
     Ready(bool),
+    ListRooms,
+    CreateRoom {
+        private: bool,
+        password: Option<String>,
+    },
+    JoinRoom {
+        id: RoomId,
+        password: Option<String>,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct PlayerStats {
+    pub wins: usize,
+    /// Rounds qualified from, not counting warmup
+    pub rounds_survived: usize,
+    pub qualifications: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Record {
+    /// Finish time in seconds
+    pub time: f32,
+    /// Empty if the record comes from old recordings
+    pub name: String,
+    /// Unix timestamp, 0 if unknown
+    pub date: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GhostFrame {
+    pub time: f32,
+    pub pos: vec2<f32>,
+    pub rot: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TrackRecord {
+    pub record: Record,
+    pub ghost: Vec<GhostFrame>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RoundResults {
+    pub round: usize,
+    /// In finish order, with seconds since the round started
+    pub finished: Vec<(Id, String, f32)>,
+    pub eliminated: Vec<(Id, String)>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct Numbers {
     pub players_left: usize,
     pub spectators: usize,
@@ -27,6 +103,47 @@ pub struct Numbers {
     pub qualified: usize,
 }
 
+/// [Player] state quantized for the wire, see [snapshot::Quantizer]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackedPlayer {
+    pub pos: [u16; 2],
+    pub vel: [i16; 2],
+    pub rot: u16,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Appearance {
+    pub skin: u16,
+    pub color: u8,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PlayerDelta {
+    pub id: Id,
+    pub state: PackedPlayer,
+    /// Only sent when it changed since the baseline
+    pub appearance: Option<Appearance>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Snapshot {
+    pub seq: u64,
+    /// Snapshot this one is a delta against, [None] means full snapshot
+    pub baseline: Option<u64>,
+    /// Server time in seconds since the room was created
+    pub time: f32,
+    pub updated: Vec<PlayerDelta>,
+    pub removed: Vec<Id>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RoomInfo {
+    pub id: RoomId,
+    pub password: bool,
+    pub clients: usize,
+    pub numbers: Numbers,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Round {
     pub num: usize,
@@ -36,17 +153,55 @@ pub struct Round {
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum ServerMessage {
+    /// First message on every connection, keep it the first variant and only append fields
+    /// so older versions can still read the protocol. Clients from before it was added decode it as [ServerMessage::Pong]
+    Hello {
+        protocol: u32,
+        level_checksum: u64,
+        /// Overrides the client's own config so both sides agree on who moves the players
+        server_authoritative: bool,
+    },
     Pong,
-    UpdatePlayer(Id, Option<Player>),
+    Snapshot(Snapshot),
     Disconnect(Id),
     YouHaveBeenEliminated,
     YouHaveBeenRespawned(vec2<f32>),
+    YouHaveBeenCorrected(vec2<f32>),
+    Reconcile(u64, Player),
     Numbers(Numbers), // TODO
     NewRound(Round),
     YouHaveBeenQualified,
+    /// Someone else qualified, with seconds since the round started
+    PlayerQualified(Id, f32),
     Name(Id, String),
     YouAreWinner,
     Winner(Option<Id>),
     YourName(String),
     RoundStarted,
+    Rooms(Vec<RoomInfo>),
+    JoinedRoom(RoomId),
+    RoomNotFound(RoomId),
+    WrongPassword(RoomId),
+    ResumeToken(String),
+    Kicked(String),
+    Announcement(String),
+    AdminResponse(String),
+    Leaderboard(Vec<(String, PlayerStats)>),
+    /// Record for the track of the round that is about to start
+    TrackRecord(Option<TrackRecord>),
+    NewRecord(Record),
+    RoundResults(RoundResults),
+    Chat(Id, String),
+    /// Chat message was dropped, can send again after that many seconds
+    ChatRateLimited(f32),
+    Emote(Id, usize),
+    YourEmote(usize),
+    /// Answer to [ClientMessage::DownloadLevel]
+    Level(Level),
+    /// Sent when joining or resuming, ids change with the room
+    YourId(Id),
+    /// Inputs arrived with a gap, send them again starting from this one
+    ResendInputs(u64),
+    /// Room was not created or joined, can try again after that many seconds
+    RoomRateLimited(f32),
 }
@@ -1,11 +1,16 @@
 use geng::prelude::*;
 
 mod bots;
+mod connection;
 mod game;
 mod interop;
 mod interpolation;
+mod match_recording;
+mod navigation;
+mod physics;
 #[cfg(not(target_arch = "wasm32"))]
 mod server;
+mod snapshot;
 mod test;
 mod ui;
 
@@ -13,7 +18,7 @@ use interop::*;
 use interpolation::*;
 use ui::*;
 
-#[derive(geng::Assets, Serialize, Deserialize)]
+#[derive(geng::Assets, Debug, Clone, Serialize, Deserialize)]
 #[asset(json)]
 pub struct Level {
     segments: Vec<[vec2<f32>; 2]>,
@@ -27,6 +32,15 @@ pub struct Track {
 }
 
 impl Level {
+    /// FNV-1a of the serialized level, the same on every platform and build
+    pub fn checksum(&self) -> u64 {
+        bincode::serialize(self)
+            .unwrap()
+            .into_iter()
+            .fold(0xcbf29ce484222325, |hash, byte| {
+                (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+            })
+    }
     pub fn random_track(&self) -> Track {
         self.random_track_from(thread_rng().gen_range(0..self.cat_locations.len()))
     }
@@ -39,7 +53,7 @@ impl Level {
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 pub struct SfxConfig {
     pub min: f32,
     pub max: f32,
@@ -52,7 +66,7 @@ impl SfxConfig {
     }
 }
 
-#[derive(geng::Assets, Deserialize)]
+#[derive(geng::Assets, Deserialize, Clone)]
 #[asset(json)]
 pub struct Config {
     pub cat_location_text: Vec<String>,
@@ -61,6 +75,21 @@ pub struct Config {
     pub nameplate_size: f32,
     pub nameplate_outline_size: f32,
     pub max_name_len: usize,
+    pub max_chat_len: usize,
+    /// Chat messages a client can send per second over time
+    pub chat_rate: f32,
+    /// Chat messages a client can send at once
+    pub chat_burst: f32,
+    /// Rooms a client can create or fail to join per second over time
+    pub room_request_rate: f32,
+    /// Rooms a client can create or fail to join at once
+    pub room_request_burst: f32,
+    /// Clients are kicked after failing to join a room this many times
+    pub max_failed_joins: usize,
+    /// Phrases shown above the car, on number keys starting from 1
+    pub emotes: Vec<String>,
+    /// Seconds between emotes of a client
+    pub emote_cooldown: f32,
     pub new_session_time: f32,
     pub rotation_speed: f32,
     pub acceleration: f32,
@@ -70,6 +99,14 @@ pub struct Config {
     pub player_radius: f32,
     pub max_speed: f32,
     pub max_backward_speed: f32,
+    pub movement_tolerance: f32,
+    pub movement_slack_time: f32,
+    /// Clients are kicked once they get this many violations
+    pub max_violations: f32,
+    /// Violations forgiven per second
+    pub violation_decay: f32,
+    /// Seconds a disconnected player keeps their slot for
+    pub reconnect_grace_period: f32,
     pub collision_bounciness: f32,
     pub camera_speed: f32,
     pub cat_move_time: i32,
@@ -79,6 +116,16 @@ pub struct Config {
     pub player_direction_scale: vec2<f32>,
     pub replay_fps: f32,
     pub server_recordings: bool,
+    /// Bots drive themselves even on tracks with recordings
+    pub ai_bots: bool,
+    pub bot_skill: bots::BotSkill,
+    pub bot_difficulty: bots::BotDifficulty,
+    /// Bots take these names in order
+    pub bot_names: Vec<String>,
+    /// Number of skins in assets/player/_list.json, counted by the server on start
+    #[serde(skip)]
+    pub skins: usize,
+    pub server_authoritative: bool,
     pub map_scale: f32,
     pub elimination_ratio: f32,
     pub bounce_sfx: SfxConfig,
@@ -105,10 +152,75 @@ pub struct Args {
     pub connect: Option<String>,
     #[clap(long)]
     pub editor: bool,
+    /// Password for admin commands sent by clients, disabled if not set
+    #[clap(long)]
+    pub admin_password: Option<String>,
+    /// Read admin commands from stdin even if it is not a terminal
+    #[clap(long)]
+    pub console: bool,
+    /// Address for the HTTP health and metrics endpoint, like 127.0.0.1:9100
+    #[clap(long)]
+    pub metrics: Option<String>,
+    /// Code of the room to join right away
+    #[clap(long)]
+    pub room: Option<String>,
+    /// Password used when creating or joining rooms
+    #[clap(long)]
+    pub room_password: Option<String>,
+    /// Directory to write a recording of every match to
+    #[clap(long)]
+    pub match_recordings: Option<std::path::PathBuf>,
+    /// Watch a match recording instead of connecting to a server
+    #[clap(long)]
+    pub replay: Option<std::path::PathBuf>,
     #[clap(flatten)]
     pub geng: geng::CliArgs,
     #[clap(long)]
     pub test: bool,
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum Command {
+    /// Inspect and edit the recorded bot replays
+    Bots(bots::cli::Args),
+}
+
+/// Decodes a value from the query string, like `a%20b` or `a+b`
+#[cfg(any(target_arch = "wasm32", test))]
+fn percent_decode(value: &str) -> String {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        match byte {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                match rest
+                    .get(..2)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                {
+                    Some(decoded) => {
+                        bytes.push(decoded);
+                        rest = &rest[2..];
+                    }
+                    None => bytes.push(byte),
+                }
+            }
+            _ => bytes.push(byte),
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[test]
+fn test_percent_decode() {
+    assert_eq!(percent_decode("a%20b+c"), "a b c");
+    assert_eq!(percent_decode("p%26ss%3D%F0%9F%90%88"), "p&ss=🐈");
+    assert_eq!(percent_decode("100%"), "100%");
+    assert_eq!(percent_decode("%zz"), "%zz");
 }
 
 fn main() {
@@ -116,7 +228,14 @@ fn main() {
     geng::setup_panic_handler();
     let mut args: Args = program_args::parse();
 
-    if args.connect.is_none() && args.server.is_none() {
+    if let Some(command) = args.command.take() {
+        match command {
+            Command::Bots(args) => bots::cli::run(args).unwrap(),
+        }
+        return;
+    }
+
+    if args.connect.is_none() && args.server.is_none() && args.replay.is_none() {
         #[cfg(target_arch = "wasm32")]
         {
             args.connect = Some(
@@ -145,6 +264,22 @@ fn main() {
         }
     }
 
+    #[cfg(target_arch = "wasm32")]
+    {
+        let search = web_sys::window().unwrap().location().search().unwrap();
+        for (key, value) in search
+            .trim_start_matches('?')
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+        {
+            match key {
+                "room" => args.room = Some(percent_decode(value)),
+                "password" => args.room_password = Some(percent_decode(value)),
+                _ => {}
+            }
+        }
+    }
+
     if args.test {
         let addr = args.connect.clone().unwrap();
         std::thread::spawn(move || test::run(&addr));
@@ -154,7 +289,7 @@ fn main() {
         #[cfg(not(target_arch = "wasm32"))]
         {
             let server =
-                geng::net::Server::new(server::App::new(), args.server.as_deref().unwrap());
+                geng::net::Server::new(server::App::new(&args), args.server.as_deref().unwrap());
             let server_handle = server.handle();
             ctrlc::set_handler(move || server_handle.shutdown()).unwrap();
             server.run();
@@ -162,7 +297,7 @@ fn main() {
     } else {
         #[cfg(not(target_arch = "wasm32"))]
         let server = if let Some(addr) = &args.server {
-            let server = geng::net::Server::new(server::App::new(), addr);
+            let server = geng::net::Server::new(server::App::new(&args), addr);
             let server_handle = server.handle();
             let server_thread = std::thread::spawn(move || {
                 server.run();
@@ -197,10 +332,31 @@ fn main() {
                 .load_asset(run_dir().join("level.json"))
                 .await
                 .expect("Failed to load level");
-            let connection = geng::net::client::connect(args.connect.as_deref().unwrap())
-                .await
-                .expect("Failed to connect to the server");
-            game::Game::new(&geng, &assets, level, &config, connection, args)
+            match args.replay.clone() {
+                Some(path) => {
+                    let recording = match_recording::Recording::load(&path)
+                        .await
+                        .unwrap_or_else(|e| panic!("Failed to load match recording {path:?}: {e}"));
+                    let mut game = game::Game::new(
+                        &geng,
+                        &assets,
+                        level,
+                        &config,
+                        connection::ServerConnection::offline(),
+                        args,
+                    );
+                    game.watch(recording);
+                    game
+                }
+                None => {
+                    let addr = args.connect.clone().unwrap();
+                    let connection = geng::net::client::connect(&addr)
+                        .await
+                        .expect("Failed to connect to the server");
+                    let connection = connection::ServerConnection::new(addr, connection);
+                    game::Game::new(&geng, &assets, level, &config, connection, args)
+                }
+            }
         });
 
         #[cfg(not(target_arch = "wasm32"))]
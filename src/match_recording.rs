@@ -0,0 +1,173 @@
+use super::*;
+
+const MAGIC: &[u8; 8] = b"COOTSREC";
+/// Bump when [Event] or [ServerMessage] change in a way old recordings can not be read
+const FORMAT_VERSION: u32 = 2;
+
+pub const EXTENSION: &str = "match";
+
+/// Message a spectator of the room got, at the time since the session started
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Event {
+    pub time: f32,
+    pub message: ServerMessage,
+}
+
+/// Start of a recording file, followed by the events one after another
+pub fn header() -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    bytes
+}
+
+/// Everything that happened in one session, starting with [ServerMessage::JoinedRoom]
+pub struct Recording {
+    pub events: Vec<Event>,
+}
+
+impl Recording {
+    pub async fn load(path: impl AsRef<std::path::Path>) -> anyhow::Result<Self> {
+        Self::from_bytes(&file::load_bytes(path).await?)
+    }
+
+    /// A recording cut short by the server stopping keeps the events before that
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        let rest = match bytes.strip_prefix(MAGIC.as_slice()) {
+            Some(rest) if rest.len() >= 4 => rest,
+            _ => anyhow::bail!("Not a match recording"),
+        };
+        let (version, mut body) = rest.split_at(4);
+        let version = u32::from_le_bytes(version.try_into().unwrap());
+        if version != FORMAT_VERSION {
+            anyhow::bail!(
+                "Recording format version {version} is not supported, expected {FORMAT_VERSION}"
+            );
+        }
+        let mut events = Vec::new();
+        while !body.is_empty() {
+            match bincode::deserialize_from(&mut body) {
+                Ok(event) => events.push(event),
+                Err(e) => {
+                    warn!("Match recording is cut short: {e}");
+                    break;
+                }
+            }
+        }
+        if events.is_empty() {
+            anyhow::bail!("Match recording is empty");
+        }
+        Ok(Self { events })
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.events.last().map_or(0.0, |event| event.time)
+    }
+}
+
+/// Position in a [Recording] being played back
+pub struct Playback {
+    recording: Recording,
+    pub time: f32,
+    pub speed: f32,
+    pub paused: bool,
+    next: usize,
+}
+
+impl Playback {
+    const SPEEDS: [f32; 6] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+
+    pub fn new(recording: Recording) -> Self {
+        Self {
+            recording,
+            time: 0.0,
+            speed: 1.0,
+            paused: false,
+            next: 0,
+        }
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.recording.duration()
+    }
+
+    /// Moves the time forward, returns how much recorded time has passed
+    pub fn advance(&mut self, delta_time: f32) -> f32 {
+        if self.paused {
+            return 0.0;
+        }
+        let old = self.time;
+        self.time = (self.time + delta_time * self.speed).min(self.duration());
+        self.time - old
+    }
+
+    /// Seeking back starts handing out events from the beginning again
+    pub fn seek(&mut self, time: f32) {
+        let time = time.clamp(0.0, self.duration());
+        if time < self.time {
+            self.next = 0;
+        }
+        self.time = time;
+    }
+
+    pub fn change_speed(&mut self, faster: bool) {
+        let index = Self::SPEEDS
+            .iter()
+            .position(|&speed| speed >= self.speed)
+            .unwrap_or(Self::SPEEDS.len() - 1);
+        let index = match faster {
+            true => (index + 1).min(Self::SPEEDS.len() - 1),
+            false => index.saturating_sub(1),
+        };
+        self.speed = Self::SPEEDS[index];
+    }
+
+    /// Next event that happened before the current time
+    pub fn next_event(&mut self) -> Option<Event> {
+        let event = self.recording.events.get(self.next)?;
+        if event.time > self.time {
+            return None;
+        }
+        self.next += 1;
+        Some(event.clone())
+    }
+}
+
+#[test]
+fn test_playback() {
+    let mut bytes = header();
+    for (time, message) in [
+        (0.0, ServerMessage::JoinedRoom("main".to_owned())),
+        (1.0, ServerMessage::RoundStarted),
+        (2.0, ServerMessage::Winner(None)),
+    ] {
+        bincode::serialize_into(&mut bytes, &Event { time, message }).unwrap();
+    }
+    // Partly written event at the end is dropped
+    let full = bytes.len();
+    bincode::serialize_into(
+        &mut bytes,
+        &Event {
+            time: 3.0,
+            message: ServerMessage::Announcement("cut".to_owned()),
+        },
+    )
+    .unwrap();
+    bytes.truncate(full + 6);
+
+    let mut playback = Playback::new(Recording::from_bytes(&bytes).unwrap());
+    assert_eq!(playback.duration(), 2.0);
+    assert_eq!(playback.advance(1.5), 1.5);
+    assert_eq!(playback.next_event().unwrap().time, 0.0);
+    assert_eq!(playback.next_event().unwrap().time, 1.0);
+    assert!(playback.next_event().is_none());
+    playback.change_speed(true);
+    assert_eq!(playback.advance(1.0), 0.5);
+    assert_eq!(playback.next_event().unwrap().time, 2.0);
+    playback.seek(0.5);
+    assert_eq!(playback.next_event().unwrap().time, 0.0);
+    assert!(playback.next_event().is_none());
+    playback.paused = true;
+    assert_eq!(playback.advance(1.0), 0.0);
+
+    assert!(Recording::from_bytes(b"COOTSBOT").is_err());
+}
@@ -0,0 +1,314 @@
+use super::*;
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// Size of a navigation grid cell
+const CELL_SIZE: f32 = 0.5;
+
+/// Grid over the level with cells too close to walls blocked
+pub struct Navigation {
+    origin: vec2<f32>,
+    size: vec2<i32>,
+    blocked: Vec<bool>,
+    /// Path distance from every cell to each of the cat locations
+    cat_distances: Vec<Vec<f32>>,
+}
+
+impl Navigation {
+    pub fn new(level: &Level, config: &Config) -> Self {
+        let bounds = Aabb2::points_bounding_box(level.segments.iter().copied().flatten())
+            .extend_uniform(CELL_SIZE);
+        let size = (bounds.size() / CELL_SIZE).map(|x| x.ceil() as i32);
+        let mut navigation = Self {
+            origin: bounds.bottom_left(),
+            size,
+            blocked: vec![false; (size.x * size.y) as usize],
+            cat_distances: vec![],
+        };
+        for &[p1, p2] in &level.segments {
+            let aabb = Aabb2::points_bounding_box([p1, p2]).extend_uniform(config.player_radius);
+            let min = navigation.cell(aabb.bottom_left());
+            let max = navigation.cell(aabb.top_right());
+            for x in min.x.max(0)..=max.x.min(size.x - 1) {
+                for y in min.y.max(0)..=max.y.min(size.y - 1) {
+                    let cell = vec2(x, y);
+                    let center = navigation.center(cell);
+                    if physics::vector_from(center, p1, p2).len() < config.player_radius {
+                        let index = navigation.index(cell);
+                        navigation.blocked[index] = true;
+                    }
+                }
+            }
+        }
+        navigation.cat_distances = level
+            .cat_locations
+            .iter()
+            .map(|&pos| navigation.distance_field(pos))
+            .collect();
+        navigation
+    }
+
+    fn cell(&self, pos: vec2<f32>) -> vec2<i32> {
+        ((pos - self.origin) / CELL_SIZE).map(|x| x.floor() as i32)
+    }
+
+    fn center(&self, cell: vec2<i32>) -> vec2<f32> {
+        self.origin + (cell.map(|x| x as f32) + vec2(0.5, 0.5)) * CELL_SIZE
+    }
+
+    fn index(&self, cell: vec2<i32>) -> usize {
+        (cell.y * self.size.x + cell.x) as usize
+    }
+
+    fn cell_at(&self, index: usize) -> vec2<i32> {
+        vec2(index as i32 % self.size.x, index as i32 / self.size.x)
+    }
+
+    fn is_free(&self, cell: vec2<i32>) -> bool {
+        cell.x >= 0
+            && cell.y >= 0
+            && cell.x < self.size.x
+            && cell.y < self.size.y
+            && !self.blocked[self.index(cell)]
+    }
+
+    /// Free cell the position belongs to, or the closest one around
+    fn free_cell(&self, pos: vec2<f32>) -> Option<vec2<i32>> {
+        const SEARCH_RADIUS: i32 = 4;
+        let cell = self.cell(pos);
+        let mut best: Option<vec2<i32>> = None;
+        for dx in -SEARCH_RADIUS..=SEARCH_RADIUS {
+            for dy in -SEARCH_RADIUS..=SEARCH_RADIUS {
+                let other = cell + vec2(dx, dy);
+                if !self.is_free(other) {
+                    continue;
+                }
+                let distance = (self.center(other) - pos).len();
+                if best.map_or(true, |best| distance < (self.center(best) - pos).len()) {
+                    best = Some(other);
+                }
+            }
+        }
+        best
+    }
+
+    fn neighbors(&self, cell: vec2<i32>) -> impl Iterator<Item = (vec2<i32>, f32)> + '_ {
+        (-1..=1)
+            .flat_map(|dx| (-1..=1).map(move |dy| vec2(dx, dy)))
+            .filter(|delta| *delta != vec2(0, 0))
+            .filter(move |delta| {
+                // No cutting corners
+                self.is_free(cell + *delta)
+                    && self.is_free(cell + vec2(delta.x, 0))
+                    && self.is_free(cell + vec2(0, delta.y))
+            })
+            .map(move |delta| (cell + delta, delta.map(|x| x as f32).len() * CELL_SIZE))
+    }
+
+    /// Path distance from every cell to the target, infinite where unreachable
+    fn distance_field(&self, target: vec2<f32>) -> Vec<f32> {
+        let mut distances = vec![f32::INFINITY; self.blocked.len()];
+        let start = match self.free_cell(target) {
+            Some(cell) => cell,
+            None => return distances,
+        };
+        let mut queue = BinaryHeap::new();
+        distances[self.index(start)] = (self.center(start) - target).len();
+        queue.push(Reverse((
+            r32(distances[self.index(start)]),
+            start.x,
+            start.y,
+        )));
+        while let Some(Reverse((distance, x, y))) = queue.pop() {
+            let cell = vec2(x, y);
+            if distance.raw() > distances[self.index(cell)] {
+                continue;
+            }
+            for (next, cost) in self.neighbors(cell) {
+                let next_distance = distance.raw() + cost;
+                let index = self.index(next);
+                if next_distance < distances[index] {
+                    distances[index] = next_distance;
+                    queue.push(Reverse((r32(next_distance), next.x, next.y)));
+                }
+            }
+        }
+        distances
+    }
+
+    /// Length of the shortest path to the given cat location
+    pub fn distance_to_cat(&self, cat: usize, pos: vec2<f32>) -> f32 {
+        let distances = &self.cat_distances[cat];
+        match self.free_cell(pos) {
+            Some(cell) => distances[self.index(cell)] + (self.center(cell) - pos).len(),
+            None => f32::INFINITY,
+        }
+    }
+
+    /// Whether the straight line between the points only passes through free cells
+    fn visible(&self, from: vec2<f32>, to: vec2<f32>) -> bool {
+        let steps = ((to - from).len() / (CELL_SIZE / 2.0)).ceil().max(1.0) as usize;
+        (0..=steps).all(|i| {
+            let pos = from + (to - from) * (i as f32 / steps as f32);
+            self.is_free(self.cell(pos))
+        })
+    }
+
+    /// Shortest path between two points, starting with `from` and ending with `to`
+    pub fn path(&self, from: vec2<f32>, to: vec2<f32>) -> Option<Vec<vec2<f32>>> {
+        let start = self.free_cell(from)?;
+        let goal = self.free_cell(to)?;
+        let heuristic = |cell: vec2<i32>| {
+            let delta = (cell - goal).map(|x| x.abs());
+            let diagonal = delta.x.min(delta.y) as f32;
+            let straight = delta.x.max(delta.y) as f32 - diagonal;
+            (straight + diagonal * std::f32::consts::SQRT_2) * CELL_SIZE
+        };
+
+        // A* over the grid
+        let mut distances = vec![f32::INFINITY; self.blocked.len()];
+        let mut came_from = vec![usize::MAX; self.blocked.len()];
+        let mut queue = BinaryHeap::new();
+        distances[self.index(start)] = 0.0;
+        queue.push(Reverse((r32(heuristic(start)), start.x, start.y)));
+        while let Some(Reverse((_, x, y))) = queue.pop() {
+            let cell = vec2(x, y);
+            if cell == goal {
+                break;
+            }
+            let distance = distances[self.index(cell)];
+            for (next, cost) in self.neighbors(cell) {
+                let next_distance = distance + cost;
+                let index = self.index(next);
+                if next_distance < distances[index] {
+                    distances[index] = next_distance;
+                    came_from[index] = self.index(cell);
+                    queue.push(Reverse((
+                        r32(next_distance + heuristic(next)),
+                        next.x,
+                        next.y,
+                    )));
+                }
+            }
+        }
+        if distances[self.index(goal)].is_infinite() {
+            return None;
+        }
+
+        let mut cells = vec![goal];
+        let mut index = self.index(goal);
+        while index != self.index(start) {
+            index = came_from[index];
+            cells.push(self.cell_at(index));
+        }
+        let points: Vec<vec2<f32>> = std::iter::once(from)
+            .chain(cells.into_iter().rev().map(|cell| self.center(cell)))
+            .chain(std::iter::once(to))
+            .collect();
+
+        // Skip every point that can be reached in a straight line
+        let mut path = vec![from];
+        let mut i = 0;
+        while i + 1 < points.len() {
+            let mut j = points.len() - 1;
+            while j > i + 1 && !self.visible(points[i], points[j]) {
+                j -= 1;
+            }
+            path.push(points[j]);
+            i = j;
+        }
+        Some(path)
+    }
+
+    /// Length of the shortest path between two points, if there is one
+    pub fn path_length(&self, from: vec2<f32>, to: vec2<f32>) -> Option<f32> {
+        let path = self.path(from, to)?;
+        Some(path.windows(2).map(|w| (w[1] - w[0]).len()).sum())
+    }
+}
+
+#[cfg(test)]
+fn test_level() -> Level {
+    serde_json::from_str(include_str!("../level.json")).unwrap()
+}
+
+/// 20x20 room split by a wall from the bottom up to y = 6
+#[cfg(test)]
+pub fn walled_room(cat_locations: Vec<vec2<f32>>) -> Level {
+    Level {
+        segments: vec![
+            [vec2(-10.0, -10.0), vec2(10.0, -10.0)],
+            [vec2(10.0, -10.0), vec2(10.0, 10.0)],
+            [vec2(10.0, 10.0), vec2(-10.0, 10.0)],
+            [vec2(-10.0, 10.0), vec2(-10.0, -10.0)],
+            [vec2(0.0, -10.0), vec2(0.0, 6.0)],
+        ],
+        cat_locations,
+    }
+}
+
+#[test]
+fn test_paths_between_cats() {
+    let config = physics::test_config();
+    let level = test_level();
+    let navigation = Navigation::new(&level, &config);
+    for (i, &from) in level.cat_locations.iter().enumerate() {
+        for (j, &to) in level.cat_locations.iter().enumerate() {
+            if i == j {
+                continue;
+            }
+            let path = navigation.path(from, to).unwrap();
+            assert_eq!(path.first(), Some(&from));
+            assert_eq!(path.last(), Some(&to));
+            for w in path.windows(2) {
+                assert!(!physics::crosses_wall(&level, w[0], w[1]));
+            }
+            let length = navigation.path_length(from, to).unwrap();
+            assert!(length >= (to - from).len() - 1e-3);
+            // Grid distances only go in 8 directions so they are a bit longer
+            let grid_length = navigation.distance_to_cat(j, from);
+            assert!(length <= grid_length + 1e-3);
+            assert!(grid_length <= length * 1.1);
+        }
+    }
+}
+
+#[test]
+fn test_path_around_wall() {
+    let config = physics::test_config();
+    let level = walled_room(vec![]);
+    let navigation = Navigation::new(&level, &config);
+    let from = vec2(-5.0, 0.0);
+    let to = vec2(5.0, 0.0);
+    let path = navigation.path(from, to).unwrap();
+    assert!(path.iter().any(|pos| pos.y > 6.0));
+    for w in path.windows(2) {
+        assert!(!physics::crosses_wall(&level, w[0], w[1]));
+    }
+    assert!(navigation.path_length(from, to).unwrap() > 2.0 * vec2(5.0, 6.0).len());
+    // Nothing is reachable outside the room
+    assert!(navigation.path(from, vec2(20.0, 0.0)).is_none());
+}
+
+#[test]
+fn test_distance_field() {
+    let config = physics::test_config();
+    let level = walled_room(vec![vec2(5.0, 0.0)]);
+    let navigation = Navigation::new(&level, &config);
+    assert!(navigation.distance_to_cat(0, vec2(5.0, 0.0)) < CELL_SIZE * 2.0);
+    // Straight to the cat on its side of the wall, around the wall from the other side
+    assert!((navigation.distance_to_cat(0, vec2(5.0, -5.0)) - 5.0).abs() < CELL_SIZE * 2.0);
+    assert!(navigation.distance_to_cat(0, vec2(-5.0, 0.0)) > 2.0 * vec2(5.0, 6.0).len());
+    assert!(navigation.distance_to_cat(0, vec2(20.0, 0.0)).is_infinite());
+    // Neighbours never differ by more than the step between them
+    let field = &navigation.cat_distances[0];
+    for (index, &distance) in field.iter().enumerate() {
+        if distance.is_infinite() {
+            continue;
+        }
+        for (next, cost) in navigation.neighbors(navigation.cell_at(index)) {
+            assert!(field[navigation.index(next)] <= distance + cost + 1e-3);
+        }
+    }
+}
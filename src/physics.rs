@@ -0,0 +1,203 @@
+use super::*;
+
+/// Fixed time step of the car simulation, same on client and server
+pub const DELTA_TIME: f32 = 1.0 / 200.0;
+
+pub fn vector_from(p: vec2<f32>, p1: vec2<f32>, p2: vec2<f32>) -> vec2<f32> {
+    if vec2::dot(p - p1, p2 - p1) < 0.0 {
+        return p1 - p;
+    }
+    if vec2::dot(p - p2, p1 - p2) < 0.0 {
+        return p2 - p;
+    }
+    let n = (p2 - p1).rotate_90();
+    // dot(p + n * t - p1, n) = 0
+    // dot(p - p1, n) + dot(n, n) * t = 0
+    let t = vec2::dot(p1 - p, n) / vec2::dot(n, n);
+    n * t
+}
+
+fn segments_intersect(a1: vec2<f32>, a2: vec2<f32>, b1: vec2<f32>, b2: vec2<f32>) -> bool {
+    let d1 = vec2::skew(b2 - b1, a1 - b1);
+    let d2 = vec2::skew(b2 - b1, a2 - b1);
+    let d3 = vec2::skew(a2 - a1, b1 - a1);
+    let d4 = vec2::skew(a2 - a1, b2 - a1);
+    d1 * d2 < 0.0 && d3 * d4 < 0.0
+}
+
+/// Whether going straight from `from` to `to` passes through a wall
+pub fn crosses_wall(level: &Level, from: vec2<f32>, to: vec2<f32>) -> bool {
+    level
+        .segments
+        .iter()
+        .any(|&[p1, p2]| segments_intersect(from, to, p1, p2))
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Collision {
+    /// Points from the wall towards the player
+    pub normal: vec2<f32>,
+    /// Velocity along the normal right before the bounce
+    pub impact: f32,
+    /// Player velocity right before the bounce
+    pub vel: vec2<f32>,
+}
+
+pub struct Step {
+    pub player: Player,
+    pub forward_vel: f32,
+    pub drift_vel: f32,
+    pub collision: Option<Collision>,
+}
+
+pub fn step(
+    player: &Player,
+    input: &PlayerInput,
+    level: &Level,
+    config: &Config,
+    delta_time: f32,
+) -> Step {
+    let mut player = player.clone();
+    player.rot += input.rotate * config.rotation_speed * delta_time;
+    let dir = vec2(1.0, 0.0).rotate(player.rot);
+
+    let mut forward_vel = vec2::dot(dir, player.vel);
+    let (target_forward_vel, forward_acceleration) = if input.accelerate > 0.0 {
+        let target_forward_vel = input.accelerate * config.max_speed;
+        let forward_acceleration = if target_forward_vel > forward_vel {
+            if forward_vel < 0.0 {
+                config.deceleration
+            } else {
+                config.acceleration
+            }
+        } else {
+            -config.deceleration
+        };
+        (target_forward_vel, forward_acceleration)
+    } else {
+        let target_forward_vel = input.accelerate * config.max_backward_speed;
+        let forward_acceleration = if target_forward_vel < forward_vel {
+            if forward_vel > 0.0 {
+                -config.deceleration
+            } else {
+                -config.backward_acceleration
+            }
+        } else {
+            config.deceleration
+        };
+        (target_forward_vel, forward_acceleration)
+    };
+    forward_vel +=
+        (target_forward_vel - forward_vel).clamp_abs(forward_acceleration.abs() * delta_time);
+
+    let mut drift_vel = vec2::skew(dir, player.vel);
+    drift_vel -= drift_vel.clamp_abs(config.drift_deceleration * delta_time);
+
+    player.vel = dir * forward_vel + dir.rotate_90() * drift_vel;
+    player.pos += player.vel * delta_time;
+
+    #[derive(PartialEq)]
+    struct Penetration {
+        n: vec2<f32>,
+        penetration: f32,
+    }
+    impl PartialOrd for Penetration {
+        fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+            Some(
+                self.penetration
+                    .partial_cmp(&other.penetration)
+                    .unwrap()
+                    .reverse(),
+            )
+        }
+    }
+    let mut deepest = None;
+    for &[p1, p2] in &level.segments {
+        let v = -vector_from(player.pos, p1, p2);
+        let penetration = config.player_radius - v.len();
+        let n = v.normalize_or_zero();
+        if penetration > 0.0 {
+            deepest = partial_max(deepest, Some(Penetration { n, penetration }));
+        }
+    }
+    let collision = deepest.map(|Penetration { n, penetration }| {
+        player.pos += n * penetration;
+        let v = vec2::dot(n, player.vel);
+        let collision = Collision {
+            normal: n,
+            impact: v,
+            vel: player.vel,
+        };
+        player.vel -= n * v * (1.0 + config.collision_bounciness);
+        collision
+    });
+
+    Step {
+        player,
+        forward_vel,
+        drift_vel,
+        collision,
+    }
+}
+
+#[cfg(test)]
+pub fn test_config() -> Config {
+    serde_json::from_str(include_str!("../config.json")).unwrap()
+}
+
+#[test]
+fn test_acceleration() {
+    let config = test_config();
+    let level = Level {
+        segments: vec![],
+        cat_locations: vec![],
+    };
+    let mut player = Player {
+        color: 0.0,
+        skin: 0,
+        pos: vec2::ZERO,
+        vel: vec2::ZERO,
+        rot: 0.0,
+    };
+    let input = PlayerInput {
+        rotate: 0.0,
+        accelerate: 1.0,
+    };
+    for _ in 0..(10.0 / DELTA_TIME) as usize {
+        player = step(&player, &input, &level, &config, DELTA_TIME).player;
+    }
+    assert!((player.vel.x - config.max_speed).abs() < 1e-3);
+    assert!(player.vel.y.abs() < 1e-3);
+    assert!(player.pos.x > 0.0);
+}
+
+#[test]
+fn test_wall_collision() {
+    let config = test_config();
+    let level = Level {
+        segments: vec![[vec2(5.0, -10.0), vec2(5.0, 10.0)]],
+        cat_locations: vec![],
+    };
+    let mut player = Player {
+        color: 0.0,
+        skin: 0,
+        pos: vec2::ZERO,
+        vel: vec2::ZERO,
+        rot: 0.0,
+    };
+    let input = PlayerInput {
+        rotate: 0.0,
+        accelerate: 1.0,
+    };
+    let mut collided = false;
+    for _ in 0..(3.0 / DELTA_TIME) as usize {
+        let result = step(&player, &input, &level, &config, DELTA_TIME);
+        if let Some(collision) = result.collision {
+            assert!(collision.normal.x < 0.0);
+            collided = true;
+        }
+        player = result.player;
+        assert!(player.pos.x <= 5.0 - config.player_radius + 1e-3);
+    }
+    assert!(collided);
+}
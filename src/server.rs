@@ -1,78 +1,267 @@
 use super::*;
 
+mod admin;
+mod driver;
+mod leaderboard;
+mod metrics;
+mod recorder;
+mod records;
+
 struct Client {
     ready: bool,
     name: String,
+    skin: usize,
+    color: f32,
     pos: Option<vec2<f32>>,
+    last_update: Timer,
+    violations: f32,
+    last_violation: Timer,
+    simulated: Option<Player>,
+    inputs: VecDeque<PlayerInput>,
+    next_input: u64,
+    input_budget: f32,
     current_replay: bots::MoveData,
+    snapshots: snapshot::Encoder,
+    /// Lets the client take this slot back after reconnecting
+    token: String,
+    /// Set while waiting for the client to reconnect
+    disconnected: Option<Timer>,
+    /// Seconds of chatting ahead of the rate limit
+    chat_debt: f32,
+    last_chat: Timer,
+    last_emote: Option<Timer>,
     sender: Box<dyn geng::net::Sender<ServerMessage>>,
 }
 
+impl Client {
+    /// Max amount of unprocessed input frames kept for a client
+    const MAX_QUEUED_INPUTS: usize = 200;
+    /// Max amount of simulation time a client can catch up in one go
+    const MAX_INPUT_BUDGET: f32 = 0.25;
+
+    fn new(sender: Box<dyn geng::net::Sender<ServerMessage>>) -> Self {
+        Self {
+            ready: false,
+            name: String::new(),
+            skin: 0,
+            color: 0.0,
+            current_replay: bots::MoveData::new(),
+            pos: None,
+            last_update: Timer::new(),
+            violations: 0.0,
+            last_violation: Timer::new(),
+            simulated: None,
+            inputs: default(),
+            next_input: 0,
+            input_budget: 0.0,
+            snapshots: default(),
+            token: format!("{:032x}", thread_rng().gen::<u128>()),
+            disconnected: None,
+            chat_debt: 0.0,
+            last_chat: Timer::new(),
+            last_emote: None,
+            sender,
+        }
+    }
+
+    fn spend_chat(&mut self, elapsed: f32, rate: f32, burst: f32) -> Result<(), f32> {
+        spend(&mut self.chat_debt, elapsed, rate, burst)
+    }
+
+    fn respawn(&mut self, pos: vec2<f32>, authoritative: bool) {
+        self.pos = Some(pos);
+        self.last_update = Timer::new();
+        self.sender.send(ServerMessage::YouHaveBeenRespawned(pos));
+        if authoritative {
+            self.next_input += self.inputs.len() as u64;
+            self.inputs.clear();
+            self.input_budget = 0.0;
+            let player = Player {
+                color: self.color,
+                skin: self.skin,
+                pos,
+                vel: vec2::ZERO,
+                rot: thread_rng().gen_range(0.0..2.0 * f32::PI),
+            };
+            self.sender
+                .send(ServerMessage::Reconcile(self.next_input, player.clone()));
+            self.simulated = Some(player);
+        }
+    }
+
+    fn push_inputs(&mut self, first: u64, inputs: Vec<PlayerInput>) {
+        let expected = self.next_input + self.inputs.len() as u64;
+        if first > expected {
+            self.sender.send(ServerMessage::ResendInputs(expected));
+            return;
+        }
+        for (seq, mut input) in (first..).zip(inputs) {
+            if seq < expected {
+                continue;
+            }
+            if !input.rotate.is_finite() {
+                input.rotate = 0.0;
+            }
+            if !input.accelerate.is_finite() {
+                input.accelerate = 0.0;
+            }
+            input.rotate = input.rotate.clamp(-1.0, 1.0);
+            input.accelerate = input.accelerate.clamp(-1.0, 1.0);
+            self.inputs.push_back(input);
+        }
+        while self.inputs.len() > Self::MAX_QUEUED_INPUTS {
+            self.inputs.pop_front();
+            self.next_input += 1;
+        }
+    }
+}
+
+/// Lets `burst` actions through at once and `rate` per second after that,
+/// returns how long to wait otherwise. `debt` is the seconds of actions ahead of the rate
+fn spend(debt: &mut f32, elapsed: f32, rate: f32, burst: f32) -> Result<(), f32> {
+    let cost = 1.0 / rate;
+    *debt = (*debt - elapsed).max(0.0);
+    let wait = *debt + cost - burst * cost;
+    if wait > 0.0 {
+        return Err(wait);
+    }
+    *debt += cost;
+    Ok(())
+}
+
+/// Stands in for the sender of a disconnected client
+struct NullSender;
+
+impl geng::net::Sender<ServerMessage> for NullSender {
+    fn send(&mut self, _message: ServerMessage) {}
+    fn send_serialized(&mut self, _data: Arc<Vec<u8>>) {}
+}
+
 struct Bot {
     index: usize,
+    name: String,
+    skin: usize,
+    color: f32,
+    /// Recording replayed in the current round
+    replay: Option<usize>,
+    /// Drives on its own when there is no recording to replay
+    driver: Option<driver::Driver>,
+}
+
+impl Bot {
+    fn new(index: usize, config: &Config) -> Self {
+        let names = &config.bot_names;
+        let name = match names.get(index % names.len().max(1)) {
+            Some(name) if index < names.len() => name.clone(),
+            Some(name) => format!("{name} {}", index / names.len() + 1),
+            None => format!("Bot {}", index + 1),
+        };
+        let mut rng = thread_rng();
+        Self {
+            index,
+            name,
+            skin: rng.gen_range(0..config.skins.max(1)),
+            color: rng.gen(),
+            replay: None,
+            driver: None,
+        }
+    }
+
+    fn appearance(&self, mut player: Player) -> Player {
+        player.skin = self.skin;
+        player.color = self.color;
+        player
+    }
 }
 
 struct State {
+    room: RoomId,
     round_countdown: Option<Timer>,
     next_id: Id,
-    level: Level,
-    config: Config,
-    bots: bots::Data,
+    level: Arc<Level>,
+    config: Arc<Config>,
+    navigation: Arc<navigation::Navigation>,
+    bots: Arc<Mutex<bots::Data>>,
+    /// Asks [save_in_background] to write bots.data
+    save_bots: std::sync::mpsc::Sender<()>,
+    /// Lowercase names banned on every room
+    bans: Arc<Mutex<HashSet<String>>>,
+    leaderboard: Arc<Mutex<leaderboard::Leaderboard>>,
+    records: Arc<Mutex<records::Records>>,
+    /// Directory to write a recording of every session to
+    match_recordings: Option<Arc<std::path::PathBuf>>,
+    recorder: Option<recorder::Recorder>,
     clients: HashMap<Id, Client>,
+    /// Latest known state of everyone racing, sent out in snapshots
+    player_states: HashMap<Id, Player>,
+    quantizer: snapshot::Quantizer,
+    time: Timer,
     round: Round,
     qualified_players: HashSet<Id>,
+    /// Qualified players of the current round with their finish times
+    finish_order: Vec<(Id, f32)>,
     bot_ids: HashMap<Id, Bot>,
     players: HashSet<Id>,
     round_timer: Option<Timer>,
     new_session_timer: Option<Timer>,
+    /// Track picked by an admin for the next round
+    next_track: Option<Track>,
     numbers: Numbers,
 }
 
 impl State {
     const TICKS_PER_SECOND: f32 = 10.0;
-    fn new() -> Self {
-        let level: Level =
-            serde_json::from_reader(std::fs::File::open(run_dir().join("level.json")).unwrap())
-                .unwrap();
-        let config: Config =
-            serde_json::from_reader(std::fs::File::open(run_dir().join("config.json")).unwrap())
-                .unwrap();
-        let mut bots = futures::executor::block_on(bots::Data::load(run_dir().join("bots.data")));
-        for (track, data) in &mut bots.0 {
-            data.retain(|data| {
-                (data.data.first().unwrap().data.pos - level.cat_locations[track.from]).len()
-                    < config.player_radius * 2.0
-                    && (data.data.last().unwrap().data.pos - level.cat_locations[track.to]).len()
-                        < config.player_radius * 2.0
-            });
-            data.sort_by_key(|data| r32(-data.data.last().unwrap().time));
-        }
-
+    fn new(
+        room: RoomId,
+        level: Arc<Level>,
+        config: Arc<Config>,
+        navigation: Arc<navigation::Navigation>,
+        bots: Arc<Mutex<bots::Data>>,
+        save_bots: std::sync::mpsc::Sender<()>,
+        bans: Arc<Mutex<HashSet<String>>>,
+        leaderboard: Arc<Mutex<leaderboard::Leaderboard>>,
+        records: Arc<Mutex<records::Records>>,
+        match_recordings: Option<Arc<std::path::PathBuf>>,
+    ) -> Self {
+        let quantizer = snapshot::Quantizer::new(&level, &config);
         let mut next_id = 0;
         let bot_ids = (0..config.min_players)
             .map(|index| {
-                let data = (next_id, Bot { index });
+                let data = (next_id, Bot::new(index, &config));
                 next_id += 1;
                 data
             })
             .collect();
         Self {
+            room,
             round_countdown: None,
             level,
             config,
+            navigation,
             bots,
+            save_bots,
+            bans,
+            leaderboard,
+            records,
+            match_recordings,
+            recorder: None,
             next_id,
             clients: default(),
+            player_states: default(),
+            quantizer,
+            time: Timer::new(),
             round: Round {
                 track: Track { from: 0, to: 1 },
                 to_be_qualified: 1,
                 num: 0,
             },
             qualified_players: default(),
+            finish_order: default(),
             players: default(),
             bot_ids,
             round_timer: None,
             new_session_timer: None,
+            next_track: None,
             numbers: Numbers {
                 players_left: 0,
                 spectators: 0,
@@ -81,22 +270,179 @@ impl State {
             },
         }
     }
+    fn add_client(&mut self, mut client: Client) -> Id {
+        for (&id, other) in &self.clients {
+            client
+                .sender
+                .send(ServerMessage::Name(id, other.name.clone()));
+        }
+        self.send_bot_names(&mut *client.sender);
+        client
+            .sender
+            .send(ServerMessage::ResumeToken(client.token.clone()));
+        let id = self.next_id;
+        self.next_id += 1;
+        client.sender.send(ServerMessage::YourId(id));
+        for other in self.clients.values_mut() {
+            other
+                .sender
+                .send(ServerMessage::Name(id, client.name.clone()));
+        }
+        self.record(ServerMessage::Name(id, client.name.clone()));
+        self.clients.insert(id, client);
+        id
+    }
+
+    fn remove_client(&mut self, id: Id) -> Option<Client> {
+        let client = self.clients.remove(&id);
+        self.players.remove(&id);
+        self.player_states.remove(&id);
+        for other in self.clients.values_mut() {
+            other.sender.send(ServerMessage::Disconnect(id));
+        }
+        self.record(ServerMessage::Disconnect(id));
+        client
+    }
+
+    fn record(&mut self, message: ServerMessage) {
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record(message);
+        }
+    }
+
+    fn send_bot_names(&self, sender: &mut dyn geng::net::Sender<ServerMessage>) {
+        for (&id, bot) in &self.bot_ids {
+            sender.send(ServerMessage::Name(id, bot.name.clone()));
+        }
+    }
+
+    /// Keep the slot of a dropped client for the grace period,
+    /// it does not count as racing until it is back
+    fn disconnect_client(&mut self, id: Id) {
+        if let Some(client) = self.clients.get_mut(&id) {
+            info!("Client {id} disconnected, waiting for them to reconnect");
+            client.sender = Box::new(NullSender);
+            client.disconnected = Some(Timer::new());
+        }
+    }
+
+    fn is_disconnected(&self, id: Id) -> bool {
+        self.clients
+            .get(&id)
+            .map_or(false, |client| client.disconnected.is_some())
+    }
+
+    fn has_session(&self, token: &str) -> bool {
+        self.clients
+            .values()
+            .any(|client| client.disconnected.is_some() && client.token == token)
+    }
+
+    /// Give a disconnected client slot to a new connection,
+    /// returns the sender back if there is no such slot anymore
+    fn resume_client(
+        &mut self,
+        token: &str,
+        mut sender: Box<dyn geng::net::Sender<ServerMessage>>,
+    ) -> Result<Id, Box<dyn geng::net::Sender<ServerMessage>>> {
+        let id = match self
+            .clients
+            .iter()
+            .find(|(_, client)| client.disconnected.is_some() && client.token == token)
+        {
+            Some((&id, _)) => id,
+            None => return Err(sender),
+        };
+        for (&other_id, other) in &self.clients {
+            if other_id != id {
+                sender.send(ServerMessage::Name(other_id, other.name.clone()));
+            }
+        }
+        self.send_bot_names(&mut *sender);
+        let client = self.clients.get_mut(&id).unwrap();
+        client.sender = sender;
+        client.disconnected = None;
+        client.snapshots = default();
+        client
+            .sender
+            .send(ServerMessage::ResumeToken(client.token.clone()));
+        client.sender.send(ServerMessage::YourId(id));
+        client
+            .sender
+            .send(ServerMessage::YourName(client.name.clone()));
+        client
+            .sender
+            .send(ServerMessage::Numbers(self.numbers.clone()));
+        if let Some(pos) = client.pos {
+            client.sender.send(ServerMessage::YouHaveBeenRespawned(pos));
+            if let Some(player) = &client.simulated {
+                client
+                    .sender
+                    .send(ServerMessage::Reconcile(client.next_input, player.clone()));
+            }
+        }
+        info!("Client {id} resumed their session");
+        Ok(id)
+    }
+
+    fn remove_expired_clients(&mut self) {
+        let grace_period = self.config.reconnect_grace_period as f64;
+        let expired: Vec<Id> = self
+            .clients
+            .iter()
+            .filter(|(_, client)| {
+                client
+                    .disconnected
+                    .as_ref()
+                    .map_or(false, |timer| timer.elapsed().as_secs_f64() > grace_period)
+            })
+            .map(|(&id, _)| id)
+            .collect();
+        for id in expired {
+            info!("Client {id} did not reconnect in time");
+            self.remove_client(id);
+        }
+    }
+
     fn tick(&mut self) {
+        self.remove_expired_clients();
+        self.update();
+        self.send_snapshots();
+    }
+
+    fn send_snapshots(&mut self) {
+        let time = self.time.elapsed().as_secs_f64() as f32;
+        let frame: snapshot::Frame = self
+            .player_states
+            .iter()
+            .map(|(&id, player)| (id, self.quantizer.pack(player)))
+            .collect();
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record_frame(frame.clone());
+        }
+        for (&id, client) in &mut self.clients {
+            let mut frame = frame.clone();
+            frame.remove(&id);
+            let snapshot = client.snapshots.encode(time, frame);
+            client.sender.send(ServerMessage::Snapshot(snapshot));
+        }
+    }
+
+    fn update(&mut self) {
         self.update_numbers();
         if let Some(timer) = &mut self.round_countdown {
             if timer.elapsed().as_secs_f64() as f32 > 3.0 {
                 let start_pos = self.level.cat_locations[self.round.track.from];
                 for id in &self.players {
                     if let Some(client) = self.clients.get_mut(id) {
-                        client.pos = Some(start_pos);
-                        client
-                            .sender
-                            .send(ServerMessage::YouHaveBeenRespawned(start_pos));
+                        client.respawn(start_pos, self.config.server_authoritative);
                     }
                 }
                 for client in self.clients.values_mut() {
                     client.sender.send(ServerMessage::RoundStarted);
                 }
+                self.record(ServerMessage::RoundStarted);
+                self.start_bots();
                 self.round_timer = Some(Timer::new());
                 self.round_countdown = None;
                 info!("Round started");
@@ -119,48 +465,108 @@ impl State {
             }
         }
 
+        if self.round_timer.is_some() && self.config.server_authoritative {
+            self.simulate_clients();
+        }
+
         if let Some(round_timer) = &self.round_timer {
-            let mut bots = self
-                .bots
-                .get(self.round.track, round_timer.elapsed().as_secs_f64() as f32);
+            let time = round_timer.elapsed().as_secs_f64() as f32;
+            let target = self.level.cat_locations[self.round.track.to];
+            let bots_data = self.bots.lock().unwrap();
             let mut bot_updates = Vec::new();
-            let mut remove_bots = Vec::new();
             for &id in &self.players {
                 if self.qualified_players.contains(&id) {
                     continue;
                 }
-                if let Some(bot) = self.bot_ids.get(&id) {
-                    if let Some(player) = bots.next() {
-                        bot_updates.push((id, player));
-                    } else {
-                        remove_bots.push(id);
-                    }
-                }
-            }
-            mem::drop(bots);
-            if !remove_bots.is_empty() {
-                for id in remove_bots {
-                    self.players.remove(&id);
+                let bot = match self.bot_ids.get_mut(&id) {
+                    Some(bot) => bot,
+                    None => continue,
+                };
+                if let Some(driver) = &mut bot.driver {
+                    driver.update(
+                        target,
+                        &self.level,
+                        &self.config,
+                        &self.navigation,
+                        1.0 / Self::TICKS_PER_SECOND,
+                    );
+                    bot_updates.push((id, driver.player.clone()));
+                } else if let Some(replay) = bot
+                    .replay
+                    .and_then(|index| bots_data.replay(self.round.track, index))
+                {
+                    let player = replay.get(time);
+                    bot_updates.push((
+                        id,
+                        match replay.driver {
+                            Some(_) => player,
+                            None => bot.appearance(player),
+                        },
+                    ));
                 }
             }
+            mem::drop(bots_data);
             for (id, player) in bot_updates {
                 self.update_player(id, player);
             }
         }
 
         if self.qualified_players.len() >= self.round.to_be_qualified
-            || self.players.len() == self.qualified_players.len()
+            || self
+                .players
+                .iter()
+                .all(|&id| self.qualified_players.contains(&id) || self.is_disconnected(id))
         {
             self.end_round();
         }
     }
+    /// Give racing bots recordings of the track matching the difficulty,
+    /// the rest drive on their own
+    fn start_bots(&mut self) {
+        let count = self
+            .players
+            .iter()
+            .filter(|id| self.bot_ids.contains_key(id))
+            .count();
+        let replays = match self.config.ai_bots {
+            true => vec![],
+            false => self.bots.lock().unwrap().pick(
+                self.round.track,
+                self.config.bot_difficulty,
+                count,
+            ),
+        };
+        let mut replays = replays.into_iter();
+        let start = self.level.cat_locations[self.round.track.from];
+        for id in &self.players {
+            let bot = match self.bot_ids.get_mut(id) {
+                Some(bot) => bot,
+                None => continue,
+            };
+            bot.replay = replays.next();
+            bot.driver = match bot.replay {
+                Some(_) => None,
+                None => Some(driver::Driver::new(
+                    bot.appearance(Player {
+                        color: 0.0,
+                        skin: 0,
+                        pos: start,
+                        vel: vec2::ZERO,
+                        rot: thread_rng().gen_range(0.0..2.0 * f32::PI),
+                    }),
+                    self.config.bot_skill.clone(),
+                )),
+            };
+        }
+    }
+
     fn new_session(&mut self) {
         info!("Starting new session");
         let start = thread_rng().gen_range(0..self.level.cat_locations.len());
         self.players = itertools::chain![
-            self.clients
-                .iter()
-                .filter_map(|(&id, client)| client.ready.then_some(id)),
+            self.clients.iter().filter_map(|(&id, client)| {
+                (client.ready && client.disconnected.is_none()).then_some(id)
+            }),
             self.bot_ids.keys().copied()
         ]
         .take(self.clients.len().max(self.config.min_players))
@@ -168,12 +574,44 @@ impl State {
         if self.players.iter().all(|id| self.bot_ids.contains_key(id)) {
             self.players.clear();
         }
+        self.start_recording();
         self.new_round_from(0, start);
     }
+    /// Replaces the recording of the previous session, which finishes writing it
+    fn start_recording(&mut self) {
+        self.recorder = None;
+        let dir = match &self.match_recordings {
+            Some(dir) if !self.players.is_empty() => dir,
+            _ => return,
+        };
+        let name = format!(
+            "{}-{}.{}",
+            bots::now(),
+            self.room,
+            match_recording::EXTENSION
+        );
+        self.recorder = Some(recorder::Recorder::start(dir.join(name)));
+        self.record(ServerMessage::JoinedRoom(self.room.clone()));
+        self.record(ServerMessage::Level((*self.level).clone()));
+        let names: Vec<(Id, String)> = itertools::chain![
+            self.clients
+                .iter()
+                .map(|(&id, client)| (id, client.name.clone())),
+            self.bot_ids.iter().map(|(&id, bot)| (id, bot.name.clone()))
+        ]
+        .collect();
+        for (id, name) in names {
+            self.record(ServerMessage::Name(id, name));
+        }
+        self.record(ServerMessage::Numbers(self.numbers.clone()));
+    }
     fn new_round_from(&mut self, num: usize, from: usize) {
         self.round = Round {
             num,
-            track: self.level.random_track_from(from),
+            track: self
+                .next_track
+                .take()
+                .unwrap_or_else(|| self.level.random_track_from(from)),
             to_be_qualified: if num == 0 {
                 self.players.len()
             } else {
@@ -183,64 +621,108 @@ impl State {
                         .min(self.players.len())
             },
         };
+        let record = self.records.lock().unwrap().get(self.round.track);
         for client in self.clients.values_mut() {
             client
                 .sender
                 .send(ServerMessage::NewRound(self.round.clone()));
+            client
+                .sender
+                .send(ServerMessage::TrackRecord(record.clone()));
         }
+        self.record(ServerMessage::NewRound(self.round.clone()));
+        self.record(ServerMessage::TrackRecord(record));
+        self.player_states.clear();
 
         info!("About to start new round...");
         self.round_countdown = Some(Timer::new());
         self.qualified_players.clear();
+        self.finish_order.clear();
     }
     fn player_finished(&mut self, id: Id) {
+        let mut new_record = None;
         if let Some(client) = self.clients.get_mut(&id) {
             client.pos = None;
             client.sender.send(ServerMessage::YouHaveBeenQualified);
+            if let Some(stats) = self.leaderboard.lock().unwrap().stats(&client.name) {
+                stats.qualifications += 1;
+            }
+            new_record = self.records.lock().unwrap().submit(
+                self.round.track,
+                &client.name,
+                &client.current_replay,
+            );
         }
+        if let Some(record) = new_record {
+            info!("New record on {:?}: {record:?}", self.round.track);
+            for client in self.clients.values_mut() {
+                client.sender.send(ServerMessage::NewRecord(record.clone()));
+            }
+            self.record(ServerMessage::NewRecord(record));
+        }
+        self.player_states.remove(&id);
+        self.qualified_players.insert(id);
+        let time = self
+            .round_timer
+            .as_ref()
+            .map_or(0.0, |timer| timer.elapsed().as_secs_f64() as f32);
+        self.finish_order.push((id, time));
         for (&client_id, client) in &mut self.clients {
             if client_id != id {
-                client.sender.send(ServerMessage::UpdatePlayer(id, None));
+                client.sender.send(ServerMessage::PlayerQualified(id, time));
             }
         }
-        self.qualified_players.insert(id);
+        self.record(ServerMessage::PlayerQualified(id, time));
     }
     fn time_up(&mut self) {
         self.end_round();
     }
 
     fn update_numbers(&mut self) {
-        let players_left = self.players.len();
+        let players_left = self
+            .players
+            .iter()
+            .filter(|&&id| !self.is_disconnected(id))
+            .count();
         let bots = self
             .players
             .iter()
             .filter(|id| self.bot_ids.contains_key(id))
             .count();
         let actual_players_left = players_left - bots;
-        let spectators = self.clients.len() - actual_players_left;
+        let connected = self
+            .clients
+            .values()
+            .filter(|client| client.disconnected.is_none())
+            .count();
+        let spectators = connected - actual_players_left;
         let qualified = self.qualified_players.len();
-        self.numbers = Numbers {
+        let numbers = Numbers {
             players_left,
             spectators,
             bots,
             qualified,
         };
+        if numbers != self.numbers {
+            self.record(ServerMessage::Numbers(numbers.clone()));
+        }
+        self.numbers = numbers;
     }
 
     fn end_round(&mut self) {
         self.round_timer = None;
+        let replays: Vec<bots::MoveData> = self
+            .clients
+            .values_mut()
+            .map(|client| mem::replace(&mut client.current_replay, bots::MoveData::new()))
+            .collect();
         if self.config.server_recordings {
-            for client in self.clients.values_mut() {
-                let replay = mem::replace(&mut client.current_replay, bots::MoveData::new());
-                self.bots.push(self.round.track, replay);
+            let mut bots = self.bots.lock().unwrap();
+            for mut replay in replays {
+                replay.date = Some(bots::now());
+                bots.push(self.round.track, replay);
             }
-            bincode::serialize_into(
-                std::io::BufWriter::new(
-                    std::fs::File::create(run_dir().join("bots.data")).unwrap(),
-                ),
-                &self.bots,
-            )
-            .unwrap();
+            self.save_bots.send(()).unwrap();
         }
 
         for (&id, client) in &mut self.clients {
@@ -250,35 +732,43 @@ impl State {
             }
         }
 
-        self.players
-            .retain(|id| self.qualified_players.contains(id));
+        self.send_round_results();
+        // Whoever is still disconnected misses the next round
+        self.players.retain(|id| {
+            self.qualified_players.contains(id)
+                && self
+                    .clients
+                    .get(id)
+                    .map_or(true, |client| client.disconnected.is_none())
+        });
+        self.player_states.clear();
+        self.record_stats();
         if self.players.len() <= 1 {
             let winner = self.players.iter().copied().next();
             for (&client_id, client) in &mut self.clients {
                 if Some(client_id) == winner {
-                    client.pos = Some(self.level.cat_locations[self.round.track.to]);
-                    client.sender.send(ServerMessage::YouHaveBeenRespawned(
+                    client.respawn(
                         self.level.cat_locations[self.round.track.to],
-                    ));
+                        self.config.server_authoritative,
+                    );
                     client.sender.send(ServerMessage::YouAreWinner);
                 } else {
                     client.sender.send(ServerMessage::Winner(winner));
                 }
             }
+            self.record(ServerMessage::Winner(winner));
             if let Some(winner) = winner {
-                if self.bot_ids.contains_key(&winner) {
-                    for client in self.clients.values_mut() {
-                        client.sender.send(ServerMessage::UpdatePlayer(
-                            winner,
-                            Some(Player {
-                                color: 0.0,
-                                skin: 0,
-                                pos: self.level.cat_locations[self.round.track.to],
-                                vel: vec2::ZERO,
-                                rot: 0.0,
-                            }),
-                        ));
-                    }
+                if let Some(bot) = self.bot_ids.get(&winner) {
+                    self.player_states.insert(
+                        winner,
+                        bot.appearance(Player {
+                            color: 0.0,
+                            skin: 0,
+                            pos: self.level.cat_locations[self.round.track.to],
+                            vel: vec2::ZERO,
+                            rot: 0.0,
+                        }),
+                    );
                 }
             }
             self.new_session_timer = Some(Timer::new());
@@ -287,6 +777,137 @@ impl State {
         }
     }
 
+    fn send_round_results(&mut self) {
+        let name = |id: Id| match self.clients.get(&id) {
+            Some(client) => client.name.clone(),
+            None => self
+                .bot_ids
+                .get(&id)
+                .map_or(String::new(), |bot| bot.name.clone()),
+        };
+        let results = RoundResults {
+            round: self.round.num,
+            finished: self
+                .finish_order
+                .iter()
+                .map(|&(id, time)| (id, name(id), time))
+                .collect(),
+            eliminated: self
+                .players
+                .iter()
+                .filter(|id| !self.qualified_players.contains(id))
+                .map(|&id| (id, name(id)))
+                .collect(),
+        };
+        for client in self.clients.values_mut() {
+            client
+                .sender
+                .send(ServerMessage::RoundResults(results.clone()));
+        }
+        self.record(ServerMessage::RoundResults(results));
+    }
+
+    /// Called once only qualified players are left in the session
+    fn record_stats(&self) {
+        let session_over = self.players.len() <= 1;
+        let mut leaderboard = self.leaderboard.lock().unwrap();
+        for id in &self.players {
+            let stats = match self
+                .clients
+                .get(id)
+                .and_then(|client| leaderboard.stats(&client.name))
+            {
+                Some(stats) => stats,
+                None => continue,
+            };
+            if self.round.num > 0 {
+                stats.rounds_survived += 1;
+            }
+            if session_over {
+                stats.wins += 1;
+            }
+        }
+        leaderboard.save();
+    }
+
+    fn simulate_clients(&mut self) {
+        let mut updates = Vec::new();
+        for (&id, client) in &mut self.clients {
+            let player = match &mut client.simulated {
+                Some(player) if client.pos.is_some() => player,
+                _ => {
+                    client.next_input += client.inputs.len() as u64;
+                    client.inputs.clear();
+                    continue;
+                }
+            };
+            client.input_budget =
+                (client.input_budget + 1.0 / Self::TICKS_PER_SECOND).min(Client::MAX_INPUT_BUDGET);
+            let mut simulated = false;
+            while client.input_budget >= physics::DELTA_TIME {
+                let input = match client.inputs.pop_front() {
+                    Some(input) => input,
+                    None => break,
+                };
+                *player = physics::step(
+                    player,
+                    &input,
+                    &self.level,
+                    &self.config,
+                    physics::DELTA_TIME,
+                )
+                .player;
+                client.next_input += 1;
+                client.input_budget -= physics::DELTA_TIME;
+                simulated = true;
+            }
+            if simulated {
+                client
+                    .sender
+                    .send(ServerMessage::Reconcile(client.next_input, player.clone()));
+                updates.push((id, player.clone()));
+            }
+        }
+        for (id, player) in updates {
+            self.update_player(id, player);
+        }
+    }
+
+    fn client_update_player(&mut self, id: Id, player: Player) {
+        let client = self
+            .clients
+            .get_mut(&id)
+            .expect("Sender not found for client");
+        if let Some(pos) = client.pos {
+            let elapsed = client.last_update.elapsed().as_secs_f64() as f32;
+            if let Err(reason) = check_movement(
+                &self.level,
+                &self.navigation,
+                &self.config,
+                pos,
+                elapsed,
+                &player,
+            ) {
+                let forgiven = client.last_violation.elapsed().as_secs_f64() as f32
+                    * self.config.violation_decay;
+                client.violations = (client.violations - forgiven).max(0.0) + 1.0;
+                client.last_violation = Timer::new();
+                warn!(
+                    "Rejected update from client {id} ({:?}): {reason}",
+                    client.name
+                );
+                client.sender.send(ServerMessage::YouHaveBeenCorrected(pos));
+                if client.violations >= self.config.max_violations {
+                    warn!("Client {id} ({:?}) flagged as cheating", client.name);
+                    self.kick(id, "Kicked for cheating");
+                }
+                return;
+            }
+            client.last_update = Timer::new();
+        }
+        self.update_player(id, player);
+    }
+
     fn update_player(&mut self, id: Id, player: Player) {
         if let Some(client) = self.clients.get(&id) {
             if client.pos.is_none() {
@@ -295,32 +916,24 @@ impl State {
             }
         }
 
-        let message = Arc::new(geng::net::serialize_message(ServerMessage::UpdatePlayer(
-            id,
-            Some(player.clone()),
-        )));
-        for (&client_id, client) in &mut self.clients {
-            if client_id == id {
-                client.pos = Some(player.pos);
-                if self.config.server_recordings {
-                    if let Some(round_timer) = &self.round_timer {
-                        if !self.qualified_players.contains(&id) {
-                            client
-                                .current_replay
-                                .push(round_timer.elapsed().as_secs_f64() as f32, player.clone());
-                        }
-                    }
+        if let Some(client) = self.clients.get_mut(&id) {
+            client.pos = Some(player.pos);
+            // Always recorded since it might be a new record
+            if let Some(round_timer) = &self.round_timer {
+                if !self.qualified_players.contains(&id) {
+                    client
+                        .current_replay
+                        .push(round_timer.elapsed().as_secs_f64() as f32, player.clone());
                 }
-            } else {
-                client.sender.send_serialized(message.clone());
             }
         }
+        self.player_states.insert(id, player.clone());
 
         self.check_finished(id, player);
     }
 
     fn check_finished(&mut self, id: Id, player: Player) {
-        if self.qualified_players.contains(&id) {
+        if self.qualified_players.contains(&id) || self.is_disconnected(id) {
             return;
         }
         if player.vel.len() > 1e-5 {
@@ -335,19 +948,298 @@ impl State {
     }
 }
 
-pub struct App {
+struct Room {
     state: Arc<Mutex<State>>,
+    /// Private rooms are not listed and can only be joined by code
+    private: bool,
+    password: Option<String>,
+}
+
+struct Rooms {
+    level: Arc<Level>,
+    config: Arc<Config>,
+    navigation: Arc<navigation::Navigation>,
+    bots: Arc<Mutex<bots::Data>>,
+    save_bots: std::sync::mpsc::Sender<()>,
+    bans: Arc<Mutex<HashSet<String>>>,
+    leaderboard: Arc<Mutex<leaderboard::Leaderboard>>,
+    records: Arc<Mutex<records::Records>>,
+    match_recordings: Option<Arc<std::path::PathBuf>>,
+    rooms: HashMap<RoomId, Room>,
+}
+
+/// Writes the file whenever something is sent to `requests` so the ticks never wait for the disk.
+/// Sending only fails once the server stops, so senders ignore the result
+fn save_in_background(
+    path: std::path::PathBuf,
+    requests: std::sync::mpsc::Receiver<()>,
+    to_bytes: impl Fn() -> anyhow::Result<Vec<u8>> + Send + 'static,
+) {
+    std::thread::spawn(move || {
+        while requests.recv().is_ok() {
+            // Everything asked for while saving is covered by the next save
+            while requests.try_recv().is_ok() {}
+            match to_bytes().and_then(|bytes| bots::write_atomic(&path, &bytes)) {
+                Ok(()) => info!("Saved {path:?}"),
+                Err(e) => error!("Failed to save {path:?}: {e:?}"),
+            }
+        }
+    });
+}
+
+/// Falls back to the backup if the file is broken,
+/// a file that can not be read is moved aside so it does not get overwritten
+fn load_json<T: serde::de::DeserializeOwned + Default>(path: &std::path::Path) -> T {
+    let read = |path: &std::path::Path| -> anyhow::Result<T> {
+        let file = std::io::BufReader::new(std::fs::File::open(path)?);
+        Ok(serde_json::from_reader(file)?)
+    };
+    if path.exists() {
+        match read(path) {
+            Ok(value) => return value,
+            Err(e) => error!("Failed to load {path:?}: {e:?}"),
+        }
+    }
+    let backup = bots::backup_path(path);
+    let value = match read(&backup) {
+        Ok(value) => {
+            warn!("Loaded the backup {backup:?}");
+            Some(value)
+        }
+        Err(_) => None,
+    };
+    if path.exists() {
+        bots::move_aside(path);
+    }
+    value.unwrap_or_default()
+}
+
+impl Rooms {
+    /// Room everyone joins on connect, always exists
+    const DEFAULT: &'static str = "main";
+    const CODE_ALPHABET: &'static [u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+    const CODE_LEN: usize = 5;
+
+    /// Room codes are case insensitive
+    fn normalize_id(id: &str) -> RoomId {
+        let id = id.trim().to_uppercase();
+        if id.eq_ignore_ascii_case(Self::DEFAULT) {
+            Self::DEFAULT.to_owned()
+        } else {
+            id
+        }
+    }
+
+    fn new(match_recordings: Option<std::path::PathBuf>) -> Self {
+        let level: Level =
+            serde_json::from_reader(std::fs::File::open(run_dir().join("level.json")).unwrap())
+                .unwrap();
+        let mut config: Config =
+            serde_json::from_reader(std::fs::File::open(run_dir().join("config.json")).unwrap())
+                .unwrap();
+        let skins: Vec<String> = serde_json::from_reader(
+            std::fs::File::open(run_dir().join("assets").join("player").join("_list.json"))
+                .unwrap(),
+        )
+        .unwrap();
+        config.skins = skins.len();
+        let mut bots = futures::executor::block_on(bots::Data::load(run_dir().join("bots.data")));
+        bots.retain_valid(&level, &config);
+        let (save_records, requests) = std::sync::mpsc::channel();
+        let records = Arc::new(Mutex::new(records::Records::load(
+            &run_dir().join("records.json"),
+            &bots,
+            save_records,
+        )));
+        save_in_background(run_dir().join("records.json"), requests, {
+            let records = records.clone();
+            move || records.lock().unwrap().to_bytes()
+        });
+        let (save_leaderboard, requests) = std::sync::mpsc::channel();
+        let leaderboard = Arc::new(Mutex::new(leaderboard::Leaderboard::load(
+            &run_dir().join("leaderboard.json"),
+            save_leaderboard,
+        )));
+        save_in_background(run_dir().join("leaderboard.json"), requests, {
+            let leaderboard = leaderboard.clone();
+            move || leaderboard.lock().unwrap().to_bytes()
+        });
+        let navigation = navigation::Navigation::new(&level, &config);
+        let bots = Arc::new(Mutex::new(bots));
+        let (save_bots_sender, requests) = std::sync::mpsc::channel();
+        save_in_background(run_dir().join("bots.data"), requests, {
+            let bots = bots.clone();
+            // Serializing takes a while, the rounds need the bots in the meantime
+            move || {
+                let bots = bots.lock().unwrap().clone();
+                Ok(bots.to_bytes())
+            }
+        });
+        let mut rooms = Self {
+            level: Arc::new(level),
+            config: Arc::new(config),
+            navigation: Arc::new(navigation),
+            bots,
+            save_bots: save_bots_sender,
+            bans: default(),
+            leaderboard,
+            records,
+            match_recordings: match_recordings.map(Arc::new),
+            rooms: default(),
+        };
+        rooms.insert(Self::DEFAULT.to_owned(), false, None);
+        rooms
+    }
+
+    fn insert(&mut self, id: RoomId, private: bool, password: Option<String>) -> Arc<Mutex<State>> {
+        let state = Arc::new(Mutex::new(State::new(
+            id.clone(),
+            self.level.clone(),
+            self.config.clone(),
+            self.navigation.clone(),
+            self.bots.clone(),
+            self.save_bots.clone(),
+            self.bans.clone(),
+            self.leaderboard.clone(),
+            self.records.clone(),
+            self.match_recordings.clone(),
+        )));
+        self.rooms.insert(
+            id,
+            Room {
+                state: state.clone(),
+                private,
+                password,
+            },
+        );
+        state
+    }
+
+    fn create(&mut self, private: bool, password: Option<String>) -> (RoomId, Arc<Mutex<State>>) {
+        let id: RoomId = loop {
+            let code: String = (0..Self::CODE_LEN)
+                .map(|_| {
+                    Self::CODE_ALPHABET[thread_rng().gen_range(0..Self::CODE_ALPHABET.len())]
+                        as char
+                })
+                .collect();
+            if !self.rooms.contains_key(&code) {
+                break code;
+            }
+        };
+        info!(
+            "Created {} room {id}",
+            if private { "private" } else { "public" }
+        );
+        let state = self.insert(id.clone(), private, password);
+        (id, state)
+    }
+
+    fn join(&self, id: &str, password: Option<&str>) -> Result<Arc<Mutex<State>>, ServerMessage> {
+        let room = self
+            .rooms
+            .get(id)
+            .ok_or_else(|| ServerMessage::RoomNotFound(id.to_owned()))?;
+        if let Some(expected) = &room.password {
+            if password != Some(expected.as_str()) {
+                return Err(ServerMessage::WrongPassword(id.to_owned()));
+            }
+        }
+        Ok(room.state.clone())
+    }
+
+    /// Public rooms, with whether they need a password
+    fn public(&self) -> Vec<(RoomId, bool, Arc<Mutex<State>>)> {
+        self.rooms
+            .iter()
+            .filter(|(_, room)| !room.private)
+            .map(|(id, room)| (id.clone(), room.password.is_some(), room.state.clone()))
+            .collect()
+    }
+
+    fn states(&self) -> Vec<Arc<Mutex<State>>> {
+        self.rooms.values().map(|room| room.state.clone()).collect()
+    }
+
+    fn all(&self) -> Vec<(RoomId, Arc<Mutex<State>>)> {
+        self.rooms
+            .iter()
+            .map(|(id, room)| (id.clone(), room.state.clone()))
+            .collect()
+    }
+
+    /// Remove rooms nobody is connected to or reconnecting to,
+    /// rooms are checked without holding the lock on all of them
+    fn cleanup(rooms: &Mutex<Self>) {
+        let all = rooms.lock().unwrap().all();
+        let empty: Vec<RoomId> = all
+            .into_iter()
+            .filter(|(id, state)| id != Self::DEFAULT && state.lock().unwrap().clients.is_empty())
+            .map(|(id, _)| id)
+            .collect();
+        let mut rooms = rooms.lock().unwrap();
+        for id in empty {
+            // Anyone who joined since then holds on to the state
+            let joined = rooms
+                .rooms
+                .get(&id)
+                .map_or(true, |room| Arc::strong_count(&room.state) > 1);
+            if !joined {
+                info!("Removing empty room {id}");
+                rooms.rooms.remove(&id);
+            }
+        }
+    }
+}
+
+pub struct App {
+    rooms: Arc<Mutex<Rooms>>,
+    admin_password: Option<String>,
+    metrics: Arc<metrics::Metrics>,
+    /// Sent in [ServerMessage::Hello] so clients can tell if their level.json differs
+    level_checksum: u64,
+    /// Sent in [ServerMessage::Hello] so clients predict the same way the server checks
+    server_authoritative: bool,
     #[allow(dead_code)]
     background_thread: std::thread::JoinHandle<()>,
 }
 
 impl App {
-    pub fn new() -> Self {
-        let state = Arc::new(Mutex::new(State::new()));
+    pub fn new(args: &Args) -> Self {
+        let rooms = Rooms::new(args.match_recordings.clone());
+        let level_checksum = rooms.level.checksum();
+        let server_authoritative = rooms.config.server_authoritative;
+        let rooms = Arc::new(Mutex::new(rooms));
+        let metrics = Arc::new(metrics::Metrics::new());
+        // Services without a terminal have nobody typing commands
+        if args.console || std::io::IsTerminal::is_terminal(&std::io::stdin()) {
+            std::thread::spawn({
+                let rooms = rooms.clone();
+                move || admin::console(rooms)
+            });
+        }
+        if let Some(addr) = args.metrics.clone() {
+            std::thread::spawn({
+                let rooms = rooms.clone();
+                let metrics = metrics.clone();
+                move || metrics::serve(addr, rooms, metrics)
+            });
+        }
         Self {
-            state: state.clone(),
+            rooms: rooms.clone(),
+            admin_password: args.admin_password.clone(),
+            metrics: metrics.clone(),
+            level_checksum,
+            server_authoritative,
             background_thread: std::thread::spawn(move || loop {
-                state.lock().unwrap().tick();
+                let timer = Timer::new();
+                let states = rooms.lock().unwrap().states();
+                for state in &states {
+                    state.lock().unwrap().tick();
+                }
+                mem::drop(states);
+                Rooms::cleanup(&rooms);
+                metrics.record_tick(timer.elapsed());
                 std::thread::sleep(std::time::Duration::from_secs_f32(
                     1.0 / State::TICKS_PER_SECOND,
                 ));
@@ -359,17 +1251,224 @@ impl App {
 pub struct ClientConnection {
     id: Id,
     state: Arc<Mutex<State>>,
+    rooms: Arc<Mutex<Rooms>>,
+    admin_password: Option<String>,
+    /// Seconds of room requests ahead of the rate limit, kept when changing rooms
+    room_debt: f32,
+    last_room_request: Timer,
+    failed_joins: usize,
+    failed_admin_logins: usize,
+}
+
+impl ClientConnection {
+    /// Clients are kicked after giving a wrong admin password this many times
+    const MAX_FAILED_ADMIN_LOGINS: usize = 3;
+
+    fn send(&mut self, message: ServerMessage) {
+        // Client might have been kicked
+        if let Some(client) = self.state.lock().unwrap().clients.get_mut(&self.id) {
+            client.sender.send(message);
+        }
+    }
+
+    fn admin(&mut self, password: String, command: String) {
+        let authorized = match &self.admin_password {
+            Some(expected) => constant_time_eq(expected.as_bytes(), password.as_bytes()),
+            None => false,
+        };
+        let response = if authorized {
+            match command.parse::<admin::Command>() {
+                Ok(command) => {
+                    info!("Client {} runs admin command {command:?}", self.id);
+                    admin::run(&self.rooms, &self.state, command)
+                }
+                Err(e) => e,
+            }
+        } else {
+            warn!("Client {} failed admin authentication", self.id);
+            self.failed_admin_logins += 1;
+            if self.failed_admin_logins >= Self::MAX_FAILED_ADMIN_LOGINS {
+                self.state
+                    .lock()
+                    .unwrap()
+                    .kick(self.id, "Too many failed admin logins");
+                return;
+            }
+            "Access denied".to_owned()
+        };
+        self.send(ServerMessage::AdminResponse(response));
+    }
+
+    fn send_level(&mut self) {
+        let level = self.rooms.lock().unwrap().level.clone();
+        self.send(ServerMessage::Level((*level).clone()));
+    }
+
+    fn list_rooms(&mut self) {
+        let public = self.rooms.lock().unwrap().public();
+        let mut rooms: Vec<RoomInfo> = public
+            .into_iter()
+            .map(|(id, password, state)| {
+                let state = state.lock().unwrap();
+                RoomInfo {
+                    id,
+                    password,
+                    clients: state.clients.len(),
+                    numbers: state.numbers.clone(),
+                }
+            })
+            .collect();
+        rooms.sort_by(|a, b| a.id.cmp(&b.id));
+        self.send(ServerMessage::Rooms(rooms));
+    }
+
+    /// Counts a room request against the rate limit, false if it has to be dropped
+    fn spend_room_request(&mut self) -> bool {
+        let config = self.rooms.lock().unwrap().config.clone();
+        let elapsed = self.last_room_request.elapsed().as_secs_f64() as f32;
+        self.last_room_request = Timer::new();
+        match spend(
+            &mut self.room_debt,
+            elapsed,
+            config.room_request_rate,
+            config.room_request_burst,
+        ) {
+            Ok(()) => true,
+            Err(wait) => {
+                self.send(ServerMessage::RoomRateLimited(wait));
+                false
+            }
+        }
+    }
+
+    fn create_room(&mut self, private: bool, password: Option<String>) {
+        if !self.spend_room_request() {
+            return;
+        }
+        let password = password.filter(|password| !password.is_empty());
+        let (id, state) = self.rooms.lock().unwrap().create(private, password);
+        self.move_to(id, state);
+    }
+
+    fn join_room(&mut self, id: RoomId, password: Option<String>) {
+        if !self.spend_room_request() {
+            return;
+        }
+        let id = Rooms::normalize_id(&id);
+        let (result, config) = {
+            let rooms = self.rooms.lock().unwrap();
+            (rooms.join(&id, password.as_deref()), rooms.config.clone())
+        };
+        match result {
+            Ok(state) => {
+                // Only failed joins count
+                self.room_debt = (self.room_debt - 1.0 / config.room_request_rate).max(0.0);
+                self.move_to(id, state);
+            }
+            Err(message) => {
+                self.send(message);
+                self.failed_joins += 1;
+                if self.failed_joins >= config.max_failed_joins {
+                    warn!("Client {} failed to join rooms too many times", self.id);
+                    self.state
+                        .lock()
+                        .unwrap()
+                        .kick(self.id, "Too many failed attempts to join a room");
+                }
+            }
+        }
+    }
+
+    fn move_to(&mut self, id: RoomId, state: Arc<Mutex<State>>) {
+        if Arc::ptr_eq(&state, &self.state) {
+            return;
+        }
+        // Never hold both rooms locked at once
+        let old = self.state.lock().unwrap().remove_client(self.id);
+        let old = match old {
+            Some(client) => client,
+            None => return,
+        };
+        let mut client = Client::new(old.sender);
+        client.name = old.name;
+        client.ready = old.ready;
+        client.skin = old.skin;
+        client.color = old.color;
+        client.sender.send(ServerMessage::JoinedRoom(id));
+        self.id = state.lock().unwrap().add_client(client);
+        self.state = state;
+    }
+
+    fn resume(&mut self, token: String) {
+        // Only lock one room at a time
+        let rooms = self.rooms.lock().unwrap().all();
+        let (room, state) = match rooms
+            .into_iter()
+            .find(|(_, state)| state.lock().unwrap().has_session(&token))
+        {
+            Some(found) => found,
+            None => {
+                warn!("Client {} tried to resume an expired session", self.id);
+                return;
+            }
+        };
+        let old = match self.state.lock().unwrap().remove_client(self.id) {
+            Some(client) => client,
+            None => return,
+        };
+        let mut sender = old.sender;
+        sender.send(ServerMessage::JoinedRoom(room));
+        let result = state.lock().unwrap().resume_client(&token, sender);
+        self.id = match result {
+            Ok(id) => id,
+            // Grace period ran out in the meantime
+            Err(sender) => {
+                let mut client = Client::new(sender);
+                client.name = old.name;
+                client.skin = old.skin;
+                client.color = old.color;
+                state.lock().unwrap().add_client(client)
+            }
+        };
+        self.state = state;
+    }
 }
 
 impl Drop for ClientConnection {
     fn drop(&mut self) {
-        let mut state = self.state.lock().unwrap();
-        state.clients.remove(&self.id);
-        state.players.remove(&self.id);
-        for other in state.clients.values_mut() {
-            other.sender.send(ServerMessage::Disconnect(self.id));
+        self.state.lock().unwrap().disconnect_client(self.id);
+    }
+}
+
+fn check_movement(
+    level: &Level,
+    navigation: &navigation::Navigation,
+    config: &Config,
+    from: vec2<f32>,
+    elapsed: f32,
+    player: &Player,
+) -> Result<(), &'static str> {
+    let max_speed = config.max_speed * config.movement_tolerance;
+    if player.vel.len() > max_speed {
+        return Err("impossible velocity");
+    }
+    let max_distance = max_speed * (elapsed + config.movement_slack_time);
+    if (player.pos - from).len() > max_distance {
+        return Err("moved too far");
+    }
+    // Turning around the end of a wall crosses it in a straight line, so look for a way around
+    if physics::crosses_wall(level, from, player.pos) {
+        match navigation.path_length(from, player.pos) {
+            Some(length) if length <= max_distance => {}
+            _ => return Err("went through a wall"),
         }
     }
+    Ok(())
+}
+
+/// Takes as long wherever the first difference is, so the password can not be guessed by timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
 }
 
 fn fix(player: &mut Player) {
@@ -392,8 +1491,27 @@ fn fix(player: &mut Player) {
 
 impl geng::net::Receiver<ClientMessage> for ClientConnection {
     fn handle(&mut self, message: ClientMessage) {
+        if !self.state.lock().unwrap().clients.contains_key(&self.id) {
+            // Kicked clients are ignored until they disconnect
+            return;
+        }
+        let message = match message {
+            ClientMessage::ListRooms => return self.list_rooms(),
+            ClientMessage::CreateRoom { private, password } => {
+                return self.create_room(private, password)
+            }
+            ClientMessage::JoinRoom { id, password } => return self.join_room(id, password),
+            ClientMessage::Resume(token) => return self.resume(token),
+            ClientMessage::Admin { password, command } => return self.admin(password, command),
+            ClientMessage::DownloadLevel => return self.send_level(),
+            message => message,
+        };
         let mut state = self.state.lock().unwrap();
         let state: &mut State = state.deref_mut();
+        if !state.clients.contains_key(&self.id) {
+            // Kicked in the meantime
+            return;
+        }
         match message {
             ClientMessage::Ready(ready) => {
                 state
@@ -417,12 +1535,53 @@ impl geng::net::Receiver<ClientMessage> for ClientConnection {
                     .send(ServerMessage::Numbers(state.numbers.clone()));
             }
             ClientMessage::UpdatePlayer(mut player) => {
-                fix(&mut player);
-                state.update_player(self.id, player);
+                if !state.config.server_authoritative {
+                    fix(&mut player);
+                    player.skin = player.skin.min(state.config.skins.max(1) - 1);
+                    state.client_update_player(self.id, player);
+                }
+            }
+            ClientMessage::Input(first, inputs) => {
+                if state.config.server_authoritative {
+                    state
+                        .clients
+                        .get_mut(&self.id)
+                        .expect("Sender not found for client")
+                        .push_inputs(first, inputs);
+                }
+            }
+            ClientMessage::Leaderboard => {
+                let top = state.leaderboard.lock().unwrap().top();
+                state
+                    .clients
+                    .get_mut(&self.id)
+                    .expect("Sender not found for client")
+                    .sender
+                    .send(ServerMessage::Leaderboard(top));
+            }
+            ClientMessage::AckSnapshot(seq) => {
+                state
+                    .clients
+                    .get_mut(&self.id)
+                    .expect("Sender not found for client")
+                    .snapshots
+                    .ack(seq);
+            }
+            ClientMessage::Appearance { skin, color } => {
+                let client = state
+                    .clients
+                    .get_mut(&self.id)
+                    .expect("Sender not found for client");
+                client.skin = skin.min(state.config.skins.max(1) - 1);
+                client.color = if color.is_finite() { color } else { 0.0 };
             }
             ClientMessage::Name(name) => {
                 let name = name.chars().filter(|c| c.is_ascii_alphabetic()).take(15);
                 let name: String = rustrict::CensorIter::censor(name).collect();
+                if state.bans.lock().unwrap().contains(&name.to_lowercase()) {
+                    state.kick(self.id, "You are banned");
+                    return;
+                }
 
                 state.clients.get_mut(&self.id).unwrap().name = name.clone();
                 for (&client_id, client) in &mut state.clients {
@@ -434,6 +1593,62 @@ impl geng::net::Receiver<ClientMessage> for ClientConnection {
                             .send(ServerMessage::Name(self.id, name.clone()));
                     }
                 }
+                state.record(ServerMessage::Name(self.id, name));
+            }
+            ClientMessage::Chat(text) => {
+                let config = state.config.clone();
+                let client = state.clients.get_mut(&self.id).unwrap();
+                let elapsed = client.last_chat.elapsed().as_secs_f64() as f32;
+                client.last_chat = Timer::new();
+                if let Err(wait) = client.spend_chat(elapsed, config.chat_rate, config.chat_burst) {
+                    client.sender.send(ServerMessage::ChatRateLimited(wait));
+                    return;
+                }
+                let text = text
+                    .chars()
+                    .filter(|c| !c.is_control())
+                    .take(config.max_chat_len);
+                let text: String = rustrict::CensorIter::censor(text).collect();
+                let text = text.trim().to_owned();
+                if text.is_empty() {
+                    return;
+                }
+                info!("Chat from {} ({:?}): {text}", self.id, client.name);
+                for client in state.clients.values_mut() {
+                    client
+                        .sender
+                        .send(ServerMessage::Chat(self.id, text.clone()));
+                }
+                state.record(ServerMessage::Chat(self.id, text));
+            }
+            ClientMessage::Emote(emote) => {
+                let cooldown = state.config.emote_cooldown as f64;
+                if emote >= state.config.emotes.len() {
+                    return;
+                }
+                let client = state.clients.get_mut(&self.id).unwrap();
+                if let Some(timer) = &client.last_emote {
+                    if timer.elapsed().as_secs_f64() < cooldown {
+                        return;
+                    }
+                }
+                client.last_emote = Some(Timer::new());
+                for (&client_id, client) in &mut state.clients {
+                    if self.id == client_id {
+                        client.sender.send(ServerMessage::YourEmote(emote));
+                    } else {
+                        client.sender.send(ServerMessage::Emote(self.id, emote));
+                    }
+                }
+                state.record(ServerMessage::Emote(self.id, emote));
+            }
+            ClientMessage::ListRooms
+            | ClientMessage::CreateRoom { .. }
+            | ClientMessage::JoinRoom { .. }
+            | ClientMessage::Resume(..)
+            | ClientMessage::Admin { .. }
+            | ClientMessage::DownloadLevel => {
+                unreachable!("Room messages are handled without locking the room")
             }
         }
     }
@@ -445,27 +1660,33 @@ impl geng::net::server::App for App {
     type ClientMessage = ClientMessage;
     fn connect(
         &mut self,
-        mut sender: Box<dyn geng::net::Sender<Self::ServerMessage>>,
+        sender: Box<dyn geng::net::Sender<Self::ServerMessage>>,
     ) -> ClientConnection {
-        let mut state = self.state.lock().unwrap();
-        for (&id, client) in &state.clients {
-            sender.send(ServerMessage::Name(id, client.name.clone()));
-        }
-        let id = state.next_id;
-        state.clients.insert(
-            id,
-            Client {
-                ready: false,
-                name: String::new(),
-                current_replay: bots::MoveData::new(),
-                pos: None,
-                sender,
-            },
-        );
-        state.next_id += 1;
+        let state = self.rooms.lock().unwrap().rooms[Rooms::DEFAULT]
+            .state
+            .clone();
+        let mut client = Client::new(Box::new(metrics::CountingSender::new(
+            sender,
+            self.metrics.clone(),
+        )));
+        client.sender.send(ServerMessage::Hello {
+            protocol: PROTOCOL_VERSION,
+            level_checksum: self.level_checksum,
+            server_authoritative: self.server_authoritative,
+        });
+        client
+            .sender
+            .send(ServerMessage::JoinedRoom(Rooms::DEFAULT.to_owned()));
+        let id = state.lock().unwrap().add_client(client);
         ClientConnection {
             id,
-            state: self.state.clone(),
+            state,
+            rooms: self.rooms.clone(),
+            admin_password: self.admin_password.clone(),
+            room_debt: 0.0,
+            last_room_request: Timer::new(),
+            failed_joins: 0,
+            failed_admin_logins: 0,
         }
     }
 }
@@ -477,3 +1698,83 @@ fn test_brainoid() {
         rustrict::CensorIter::censor("brainoid".chars()).collect::<String>()
     );
 }
+
+#[test]
+fn test_chat_rate_limit() {
+    let mut client = Client::new(Box::new(NullSender));
+    for _ in 0..3 {
+        assert!(client.spend_chat(0.0, 0.5, 3.0).is_ok());
+    }
+    let wait = client.spend_chat(0.0, 0.5, 3.0).unwrap_err();
+    assert!((wait - 2.0).abs() < 1e-5);
+    assert!(client.spend_chat(1.0, 0.5, 3.0).is_err());
+    assert!(client.spend_chat(1.0, 0.5, 3.0).is_ok());
+    assert!(client.spend_chat(0.0, 0.5, 3.0).is_err());
+}
+
+#[test]
+fn test_constant_time_eq() {
+    assert!(constant_time_eq(b"secret", b"secret"));
+    assert!(!constant_time_eq(b"secret", b"secreT"));
+    assert!(!constant_time_eq(b"secret", b"secret2"));
+    assert!(!constant_time_eq(b"", b"secret"));
+}
+
+#[test]
+fn test_input_gap() {
+    let mut client = Client::new(Box::new(NullSender));
+    let input = PlayerInput {
+        rotate: 0.0,
+        accelerate: 1.0,
+    };
+    client.push_inputs(0, vec![input; 2]);
+    client.push_inputs(1, vec![input; 2]);
+    assert_eq!(client.inputs.len(), 3);
+    client.push_inputs(5, vec![input]);
+    assert_eq!(client.inputs.len(), 3);
+}
+
+#[test]
+fn test_check_movement() {
+    let config = physics::test_config();
+    let level = Level {
+        segments: vec![[vec2(5.0, -10.0), vec2(5.0, 10.0)]],
+        cat_locations: vec![],
+    };
+    let navigation = navigation::Navigation::new(&level, &config);
+    let check = |from, pos, vel| {
+        let player = Player {
+            color: 0.0,
+            skin: 0,
+            pos,
+            vel,
+            rot: 0.0,
+        };
+        check_movement(&level, &navigation, &config, from, 0.1, &player).is_ok()
+    };
+    assert!(check(vec2::ZERO, vec2(1.0, 0.0), vec2(10.0, 0.0)));
+    assert!(!check(vec2::ZERO, vec2(1.0, 0.0), vec2(100.0, 0.0)));
+    assert!(!check(vec2::ZERO, vec2(0.0, 9.0), vec2::ZERO));
+    assert!(!check(vec2(4.0, 0.0), vec2(6.0, 0.0), vec2::ZERO));
+}
+
+#[test]
+fn test_u_turn_around_wall() {
+    let config = physics::test_config();
+    let level = navigation::walled_room(vec![]);
+    let navigation = navigation::Navigation::new(&level, &config);
+    let check = |from, pos| {
+        let player = Player {
+            color: 0.0,
+            skin: 0,
+            pos,
+            vel: vec2::ZERO,
+            rot: 0.0,
+        };
+        check_movement(&level, &navigation, &config, from, 0.1, &player).is_ok()
+    };
+    // Around the end of the wall at y = 6
+    assert!(check(vec2(-1.5, 5.5), vec2(1.5, 5.5)));
+    // The way around is much longer than the jump
+    assert!(!check(vec2(-1.5, -5.0), vec2(1.5, -5.0)));
+}
@@ -0,0 +1,369 @@
+use super::*;
+
+const HELP: &str = "commands: room <code>, list, kick <id|name>, ban <id|name>, unban <name>, \
+                    newsession, skip, track <from> <to>, bots <count>, \
+                    difficulty <easy|normal|hard|0-100>, recordings [on|off], records, \
+                    say <message>";
+
+#[derive(Debug)]
+pub enum Target {
+    Id(Id),
+    Name(String),
+}
+
+impl Target {
+    /// Names only consist of letters so anything numeric is an id
+    fn parse(s: &str) -> Self {
+        match s.parse() {
+            Ok(id) => Self::Id(id),
+            Err(_) => Self::Name(s.to_owned()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Command {
+    Help,
+    /// Select the room the console commands apply to
+    Room(RoomId),
+    List,
+    Kick(Target),
+    Ban(Target),
+    Unban(String),
+    NewSession,
+    /// Restart the current round, on the picked track if there is one
+    Skip,
+    Track(Track),
+    MinPlayers(usize),
+    Difficulty(bots::BotDifficulty),
+    /// Toggle when not specified
+    Recordings(Option<bool>),
+    Records,
+    Say(String),
+}
+
+impl std::str::FromStr for Command {
+    type Err = String;
+    fn from_str(line: &str) -> Result<Self, String> {
+        let line = line.trim();
+        let (name, rest) = match line.split_once(' ') {
+            Some((name, rest)) => (name, rest.trim()),
+            None => (line, ""),
+        };
+        let arg = || {
+            if rest.is_empty() {
+                Err(format!("{name}: missing argument"))
+            } else {
+                Ok(rest)
+            }
+        };
+        Ok(match name {
+            "help" => Self::Help,
+            "room" => Self::Room(arg()?.to_owned()),
+            "list" => Self::List,
+            "kick" => Self::Kick(Target::parse(arg()?)),
+            "ban" => Self::Ban(Target::parse(arg()?)),
+            "unban" => Self::Unban(arg()?.to_owned()),
+            "newsession" => Self::NewSession,
+            "skip" => Self::Skip,
+            "track" => {
+                let usage = || "usage: track <from> <to>".to_owned();
+                let (from, to) = arg()?.split_once(' ').ok_or_else(usage)?;
+                Self::Track(Track {
+                    from: from.trim().parse().map_err(|_| usage())?,
+                    to: to.trim().parse().map_err(|_| usage())?,
+                })
+            }
+            "bots" => Self::MinPlayers(
+                arg()?
+                    .parse()
+                    .map_err(|_| "usage: bots <count>".to_owned())?,
+            ),
+            "difficulty" => Self::Difficulty(arg()?.parse()?),
+            "recordings" => Self::Recordings(match rest {
+                "" => None,
+                "on" => Some(true),
+                "off" => Some(false),
+                _ => return Err("usage: recordings [on|off]".to_owned()),
+            }),
+            "records" => Self::Records,
+            "say" => Self::Say(arg()?.to_owned()),
+            _ => return Err(format!("unknown command {name:?}, try help")),
+        })
+    }
+}
+
+/// Reads commands from stdin, they apply to the selected room
+pub fn console(rooms: Arc<Mutex<Rooms>>) {
+    let mut room = Rooms::DEFAULT.to_owned();
+    for line in std::io::stdin().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match line.parse::<Command>() {
+            Ok(Command::Room(id)) => {
+                let id = Rooms::normalize_id(&id);
+                if rooms.lock().unwrap().rooms.contains_key(&id) {
+                    room = id;
+                    format!("Selected room {room}")
+                } else {
+                    format!("Room {id} not found")
+                }
+            }
+            Ok(command) => {
+                let state = rooms
+                    .lock()
+                    .unwrap()
+                    .rooms
+                    .get(&room)
+                    .map(|room| room.state.clone());
+                match state {
+                    Some(state) => run(&rooms, &state, command),
+                    None => format!("Room {room} is gone, select another one"),
+                }
+            }
+            Err(e) => e,
+        };
+        info!("Console command {line:?}");
+        println!("{response}");
+    }
+}
+
+/// Run a command against the given room, returns the response for the operator
+pub fn run(rooms: &Mutex<Rooms>, state: &Mutex<State>, command: Command) -> String {
+    match command {
+        Command::Help => HELP.to_owned(),
+        Command::Room(_) => "Room selection is only available in the console".to_owned(),
+        Command::List => {
+            let state = state.lock().unwrap();
+            let mut ids: Vec<Id> = state.clients.keys().copied().collect();
+            ids.sort();
+            let lines: Vec<String> = ids
+                .into_iter()
+                .map(|id| {
+                    let client = &state.clients[&id];
+                    format!(
+                        "{id} {:?}{}{}{}",
+                        client.name,
+                        if client.ready { " ready" } else { "" },
+                        if client.pos.is_some() { " racing" } else { "" },
+                        if client.disconnected.is_some() {
+                            " disconnected"
+                        } else {
+                            ""
+                        },
+                    )
+                })
+                .collect();
+            if lines.is_empty() {
+                "Nobody is here".to_owned()
+            } else {
+                lines.join("\n")
+            }
+        }
+        Command::Kick(target) => {
+            let mut state = state.lock().unwrap();
+            match state.find_client(&target) {
+                Some(id) => {
+                    let name = state.kick(id, "Kicked by admin").unwrap_or_default();
+                    format!("Kicked {id} ({name:?})")
+                }
+                None => format!("No client {target:?}"),
+            }
+        }
+        Command::Ban(target) => {
+            let name = {
+                let state = state.lock().unwrap();
+                match state.find_client(&target) {
+                    Some(id) => state.clients[&id].name.clone(),
+                    None => match target {
+                        Target::Name(name) => name,
+                        Target::Id(id) => return format!("No client {id}"),
+                    },
+                }
+            };
+            if name.is_empty() {
+                return "Can not ban clients without a name".to_owned();
+            }
+            let name = name.to_lowercase();
+            let bans = rooms.lock().unwrap().bans.clone();
+            bans.lock().unwrap().insert(name.clone());
+            let states = rooms.lock().unwrap().states();
+            let mut kicked = 0;
+            for state in states {
+                let mut state = state.lock().unwrap();
+                let ids: Vec<Id> = state
+                    .clients
+                    .iter()
+                    .filter(|(_, client)| client.name.to_lowercase() == name)
+                    .map(|(&id, _)| id)
+                    .collect();
+                for id in ids {
+                    state.kick(id, "You are banned");
+                    kicked += 1;
+                }
+            }
+            format!("Banned {name:?}, kicked {kicked} clients")
+        }
+        Command::Unban(name) => {
+            let bans = rooms.lock().unwrap().bans.clone();
+            let removed = bans.lock().unwrap().remove(&name.to_lowercase());
+            if removed {
+                format!("Unbanned {name:?}")
+            } else {
+                format!("{name:?} was not banned")
+            }
+        }
+        Command::NewSession => {
+            state.lock().unwrap().force_new_session();
+            "Started new session".to_owned()
+        }
+        Command::Skip => {
+            state.lock().unwrap().skip_round();
+            "Skipped round".to_owned()
+        }
+        Command::Track(track) => {
+            let mut state = state.lock().unwrap();
+            let locations = state.level.cat_locations.len();
+            if track.from >= locations || track.to >= locations || track.from == track.to {
+                return format!("Track must be two different locations below {locations}");
+            }
+            state.next_track = Some(track);
+            format!("Next round goes from {} to {}", track.from, track.to)
+        }
+        Command::MinPlayers(min_players) => {
+            state.lock().unwrap().set_min_players(min_players);
+            format!("Min players set to {min_players}")
+        }
+        Command::Difficulty(difficulty) => {
+            let mut state = state.lock().unwrap();
+            Arc::make_mut(&mut state.config).bot_difficulty = difficulty;
+            format!("Bot difficulty set to {difficulty:?}, applies from the next round")
+        }
+        Command::Recordings(enabled) => {
+            let mut state = state.lock().unwrap();
+            let config = Arc::make_mut(&mut state.config);
+            config.server_recordings = enabled.unwrap_or(!config.server_recordings);
+            format!(
+                "Recordings {}",
+                if config.server_recordings {
+                    "on"
+                } else {
+                    "off"
+                }
+            )
+        }
+        Command::Records => {
+            let records = rooms.lock().unwrap().records.clone();
+            let table = records.lock().unwrap().table();
+            if table.is_empty() {
+                return "No records yet".to_owned();
+            }
+            table
+                .into_iter()
+                .map(|(track, record)| {
+                    format!(
+                        "{} -> {}: {:.2}s {:?} at {}",
+                        track.from, track.to, record.time, record.name, record.date
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+        Command::Say(message) => {
+            let states = rooms.lock().unwrap().states();
+            for state in states {
+                let mut state = state.lock().unwrap();
+                for client in state.clients.values_mut() {
+                    client
+                        .sender
+                        .send(ServerMessage::Announcement(message.clone()));
+                }
+                state.record(ServerMessage::Announcement(message.clone()));
+            }
+            "Message sent".to_owned()
+        }
+    }
+}
+
+impl State {
+    fn find_client(&self, target: &Target) -> Option<Id> {
+        match target {
+            Target::Id(id) => self.clients.contains_key(id).then_some(*id),
+            Target::Name(name) => self
+                .clients
+                .iter()
+                .find(|(_, client)| client.name.eq_ignore_ascii_case(name))
+                .map(|(&id, _)| id),
+        }
+    }
+
+    /// Returns the name of the kicked client
+    pub(super) fn kick(&mut self, id: Id, reason: &str) -> Option<String> {
+        let mut client = self.remove_client(id)?;
+        info!("Kicked client {id} ({:?}): {reason}", client.name);
+        client.sender.send(ServerMessage::Kicked(reason.to_owned()));
+        Some(client.name)
+    }
+
+    fn abort_round(&mut self) {
+        self.round_timer = None;
+        self.round_countdown = None;
+        self.new_session_timer = None;
+        for client in self.clients.values_mut() {
+            client.pos = None;
+        }
+        self.player_states.clear();
+        self.qualified_players.clear();
+        self.finish_order.clear();
+    }
+
+    fn force_new_session(&mut self) {
+        self.abort_round();
+        self.new_session();
+    }
+
+    fn skip_round(&mut self) {
+        self.abort_round();
+        if self.players.len() <= 1 {
+            self.new_session();
+        } else {
+            self.new_round_from(self.round.num, self.round.track.from);
+        }
+    }
+
+    fn set_min_players(&mut self, min_players: usize) {
+        Arc::make_mut(&mut self.config).min_players = min_players;
+        let mut bots: Vec<(Id, usize)> = self
+            .bot_ids
+            .iter()
+            .map(|(&id, bot)| (id, bot.index))
+            .collect();
+        bots.sort_by_key(|&(_, index)| index);
+        for &(id, _) in bots.iter().skip(min_players) {
+            self.bot_ids.remove(&id);
+            self.players.remove(&id);
+            self.player_states.remove(&id);
+            self.qualified_players.remove(&id);
+            for client in self.clients.values_mut() {
+                client.sender.send(ServerMessage::Disconnect(id));
+            }
+            self.record(ServerMessage::Disconnect(id));
+        }
+        for index in bots.len()..min_players {
+            let bot = Bot::new(index, &self.config);
+            for client in self.clients.values_mut() {
+                client
+                    .sender
+                    .send(ServerMessage::Name(self.next_id, bot.name.clone()));
+            }
+            self.record(ServerMessage::Name(self.next_id, bot.name.clone()));
+            self.bot_ids.insert(self.next_id, bot);
+            self.next_id += 1;
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use super::*;
+
+/// Seconds between path updates
+const REPLAN_INTERVAL: f32 = 0.5;
+/// Waypoints closer than that count as reached
+const WAYPOINT_RADIUS: f32 = 1.5;
+/// Steering per radian of heading error
+const STEERING: f32 = 3.0;
+/// How much speed is lost on a U-turn compared to going straight
+const CORNER_SLOWDOWN: f32 = 0.7;
+const STUCK_SPEED: f32 = 1.0;
+const STUCK_TIME: f32 = 1.0;
+const REVERSE_TIME: f32 = 0.7;
+
+/// Bot driving its car with the same physics as players
+pub struct Driver {
+    pub player: Player,
+    skill: bots::BotSkill,
+    waypoints: Vec<vec2<f32>>,
+    next_replan: f32,
+    noise: f32,
+    stuck_time: f32,
+    reverse_time: f32,
+}
+
+/// Angle in -PI..PI
+fn normalize_angle(angle: f32) -> f32 {
+    (angle + f32::PI).rem_euclid(2.0 * f32::PI) - f32::PI
+}
+
+impl Driver {
+    pub fn new(player: Player, skill: bots::BotSkill) -> Self {
+        Self {
+            player,
+            skill,
+            waypoints: vec![],
+            next_replan: 0.0,
+            noise: 0.0,
+            stuck_time: 0.0,
+            reverse_time: 0.0,
+        }
+    }
+
+    /// Drive towards the target for the given time,
+    /// input only changes once per call like a player reacting
+    pub fn update(
+        &mut self,
+        target: vec2<f32>,
+        level: &Level,
+        config: &Config,
+        navigation: &navigation::Navigation,
+        delta_time: f32,
+    ) {
+        let input = self.input(target, config, navigation, delta_time);
+        for _ in 0..(delta_time / physics::DELTA_TIME).round() as usize {
+            self.player =
+                physics::step(&self.player, &input, level, config, physics::DELTA_TIME).player;
+        }
+    }
+
+    fn input(
+        &mut self,
+        target: vec2<f32>,
+        config: &Config,
+        navigation: &navigation::Navigation,
+        delta_time: f32,
+    ) -> PlayerInput {
+        let pos = self.player.pos;
+        self.next_replan -= delta_time;
+        if self.next_replan < 0.0 {
+            self.next_replan = REPLAN_INTERVAL;
+            self.waypoints = match navigation.path(pos, target) {
+                Some(path) => path.into_iter().skip(1).collect(),
+                None => vec![target],
+            };
+        }
+        while self.waypoints.len() > 1 && (self.waypoints[0] - pos).len() < WAYPOINT_RADIUS {
+            self.waypoints.remove(0);
+        }
+        let waypoint = self.waypoints.first().copied().unwrap_or(target);
+
+        let direction = (waypoint - pos).arg();
+        let error = normalize_angle(direction - self.player.rot);
+        self.noise =
+            self.noise * 0.8 + thread_rng().gen_range(-1.0..=1.0) * self.skill.noise * 0.5;
+        let rotate = (error * STEERING + self.noise).clamp(-1.0, 1.0);
+
+        // Fastest speed that still lets us slow down in time
+        let braking_speed = |distance: f32, end_speed: f32| {
+            (end_speed * end_speed + 2.0 * config.deceleration * distance).sqrt()
+        };
+        let mut speed = config.max_speed;
+        if let Some(&next) = self.waypoints.get(1) {
+            let turn = normalize_angle((next - waypoint).arg() - direction).abs() / f32::PI;
+            let corner_speed = config.max_speed * (1.0 - turn * CORNER_SLOWDOWN);
+            let allowed = braking_speed((waypoint - pos).len(), corner_speed);
+            if allowed < speed {
+                speed += (allowed - speed) * self.skill.cornering;
+            }
+        }
+        let target_distance = (target - pos).len();
+        speed = speed.min(braking_speed(
+            (target_distance - config.player_radius).max(0.0),
+            0.0,
+        ));
+        // Turn around before speeding up
+        speed *= error.cos().max(0.2);
+        let mut accelerate = (speed / config.max_speed * self.skill.speed).clamp(0.0, 1.0);
+        if target_distance < config.player_radius {
+            accelerate = 0.0;
+        }
+
+        if self.reverse_time > 0.0 {
+            self.reverse_time -= delta_time;
+            return PlayerInput {
+                rotate: -rotate,
+                accelerate: -1.0,
+            };
+        }
+        if self.player.vel.len() < STUCK_SPEED
+            && target_distance > config.player_radius * 2.0
+            && accelerate > 0.1
+        {
+            self.stuck_time += delta_time;
+            if self.stuck_time > STUCK_TIME {
+                self.stuck_time = 0.0;
+                self.reverse_time = REVERSE_TIME;
+            }
+        } else {
+            self.stuck_time = 0.0;
+        }
+        PlayerInput { rotate, accelerate }
+    }
+}
+
+#[test]
+fn test_drives_every_track() {
+    let config = physics::test_config();
+    let level: Level = serde_json::from_str(include_str!("../../level.json")).unwrap();
+    let navigation = navigation::Navigation::new(&level, &config);
+    let delta_time = 1.0 / State::TICKS_PER_SECOND;
+    for from in 0..level.cat_locations.len() {
+        for to in 0..level.cat_locations.len() {
+            if from == to {
+                continue;
+            }
+            let target = level.cat_locations[to];
+            let player = Player {
+                color: 0.0,
+                skin: 0,
+                pos: level.cat_locations[from],
+                vel: vec2::ZERO,
+                rot: 0.0,
+            };
+            // Noise is random, without it every run drives the same way
+            let skill = bots::BotSkill {
+                noise: 0.0,
+                ..config.bot_skill.clone()
+            };
+            let mut driver = Driver::new(player, skill);
+            let mut time = 0.0;
+            loop {
+                driver.update(target, &level, &config, &navigation, delta_time);
+                time += delta_time;
+                let player = &driver.player;
+                if player.vel.len() < 1e-5
+                    && (player.pos - target).len() < config.player_radius * 2.0
+                {
+                    break;
+                }
+                assert!(
+                    time < config.cat_move_time as f32,
+                    "bot did not get from {from} to {to} in time",
+                );
+            }
+        }
+    }
+}
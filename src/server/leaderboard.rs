@@ -0,0 +1,50 @@
+use super::*;
+
+/// Stats per player name, persisted across sessions
+pub struct Leaderboard {
+    players: HashMap<String, PlayerStats>,
+    save: std::sync::mpsc::Sender<()>,
+}
+
+impl Leaderboard {
+    /// Amount of entries sent to clients
+    const TOP: usize = 10;
+
+    pub fn load(path: &std::path::Path, save: std::sync::mpsc::Sender<()>) -> Self {
+        Self {
+            players: load_json(path),
+            save,
+        }
+    }
+
+    pub fn save(&self) {
+        let _ = self.save.send(());
+    }
+
+    pub fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        Ok(serde_json::to_vec_pretty(&self.players)?)
+    }
+
+    /// Players without a name are not tracked
+    pub fn stats(&mut self, name: &str) -> Option<&mut PlayerStats> {
+        if name.is_empty() {
+            return None;
+        }
+        Some(self.players.entry(name.to_owned()).or_default())
+    }
+
+    pub fn top(&self) -> Vec<(String, PlayerStats)> {
+        let mut players: Vec<(String, PlayerStats)> = self
+            .players
+            .iter()
+            .map(|(name, stats)| (name.clone(), stats.clone()))
+            .collect();
+        players.sort_by(|(a_name, a), (b_name, b)| {
+            (b.wins, b.rounds_survived, b.qualifications)
+                .cmp(&(a.wins, a.rounds_survived, a.qualifications))
+                .then_with(|| a_name.cmp(b_name))
+        });
+        players.truncate(Self::TOP);
+        players
+    }
+}
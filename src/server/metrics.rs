@@ -0,0 +1,300 @@
+use super::*;
+
+use std::fmt::Write as _;
+use std::io::{BufRead, Write as _};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Health is reported bad if the tick thread has not ticked for this long
+const MAX_TICK_AGE: f64 = 5.0;
+
+struct Rates {
+    timer: Timer,
+    messages: u64,
+    bytes: u64,
+    messages_per_second: f64,
+    bytes_per_second: f64,
+}
+
+pub struct Metrics {
+    started: Timer,
+    messages_sent: AtomicU64,
+    bytes_sent: AtomicU64,
+    ticks: AtomicU64,
+    tick_overruns: AtomicU64,
+    last_tick_duration_micros: AtomicU64,
+    /// Time of the last tick since start
+    last_tick_millis: AtomicU64,
+    rates: Mutex<Rates>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            started: Timer::new(),
+            messages_sent: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            ticks: AtomicU64::new(0),
+            tick_overruns: AtomicU64::new(0),
+            last_tick_duration_micros: AtomicU64::new(0),
+            last_tick_millis: AtomicU64::new(0),
+            rates: Mutex::new(Rates {
+                timer: Timer::new(),
+                messages: 0,
+                bytes: 0,
+                messages_per_second: 0.0,
+                bytes_per_second: 0.0,
+            }),
+        }
+    }
+
+    pub fn record_tick(&self, duration: std::time::Duration) {
+        self.ticks.fetch_add(1, Ordering::Relaxed);
+        if duration.as_secs_f32() > 1.0 / State::TICKS_PER_SECOND {
+            self.tick_overruns.fetch_add(1, Ordering::Relaxed);
+        }
+        self.last_tick_duration_micros
+            .store(duration.as_micros() as u64, Ordering::Relaxed);
+        self.last_tick_millis
+            .store(self.started.elapsed().as_millis() as u64, Ordering::Relaxed);
+
+        let mut rates = self.rates.lock().unwrap();
+        let elapsed = rates.timer.elapsed().as_secs_f64();
+        if elapsed >= 1.0 {
+            let messages = self.messages_sent.load(Ordering::Relaxed);
+            let bytes = self.bytes_sent.load(Ordering::Relaxed);
+            rates.messages_per_second = (messages - rates.messages) as f64 / elapsed;
+            rates.bytes_per_second = (bytes - rates.bytes) as f64 / elapsed;
+            rates.messages = messages;
+            rates.bytes = bytes;
+            rates.timer = Timer::new();
+        }
+    }
+
+    fn healthy(&self) -> bool {
+        let now = self.started.elapsed().as_secs_f64();
+        let last_tick = self.last_tick_millis.load(Ordering::Relaxed) as f64 / 1000.0;
+        now - last_tick < MAX_TICK_AGE
+    }
+}
+
+/// Counts everything sent to a client
+pub struct CountingSender {
+    inner: Box<dyn geng::net::Sender<ServerMessage>>,
+    metrics: Arc<Metrics>,
+}
+
+impl CountingSender {
+    pub fn new(inner: Box<dyn geng::net::Sender<ServerMessage>>, metrics: Arc<Metrics>) -> Self {
+        Self { inner, metrics }
+    }
+}
+
+impl geng::net::Sender<ServerMessage> for CountingSender {
+    fn send(&mut self, message: ServerMessage) {
+        self.send_serialized(Arc::new(geng::net::serialize_message(message)));
+    }
+    fn send_serialized(&mut self, data: Arc<Vec<u8>>) {
+        self.metrics.messages_sent.fetch_add(1, Ordering::Relaxed);
+        self.metrics
+            .bytes_sent
+            .fetch_add(data.len() as u64, Ordering::Relaxed);
+        self.inner.send_serialized(data);
+    }
+}
+
+/// Serves `/health` and `/metrics` (Prometheus text format) over plain HTTP
+pub fn serve(addr: String, rooms: Arc<Mutex<Rooms>>, metrics: Arc<Metrics>) {
+    let listener = match std::net::TcpListener::bind(&addr) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Failed to start metrics server on {addr}: {e}");
+            return;
+        }
+    };
+    info!("Serving metrics on http://{addr}/metrics");
+    for stream in listener.incoming() {
+        let result = stream.and_then(|stream| handle(stream, &rooms, &metrics));
+        if let Err(e) = result {
+            warn!("Metrics request failed: {e}");
+        }
+    }
+}
+
+fn handle(
+    mut stream: std::net::TcpStream,
+    rooms: &Mutex<Rooms>,
+    metrics: &Metrics,
+) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(std::time::Duration::from_secs(5)))?;
+    let mut reader = std::io::BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+    let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+    let (status, body) = match path {
+        "/health" => {
+            if metrics.healthy() {
+                ("200 OK", "ok\n".to_owned())
+            } else {
+                (
+                    "503 Service Unavailable",
+                    "tick thread stalled\n".to_owned(),
+                )
+            }
+        }
+        "/metrics" => ("200 OK", render(rooms, metrics)),
+        _ => ("404 Not Found", "not found\n".to_owned()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\n\
+         Content-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n\
+         {body}",
+        body.len(),
+    )
+}
+
+fn metric(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    values: impl IntoIterator<Item = (String, f64)>,
+) {
+    writeln!(out, "# HELP {name} {help}").unwrap();
+    writeln!(out, "# TYPE {name} {kind}").unwrap();
+    for (labels, value) in values {
+        writeln!(out, "{name}{labels} {value}").unwrap();
+    }
+}
+
+fn render(rooms: &Mutex<Rooms>, metrics: &Metrics) -> String {
+    struct RoomStats {
+        labels: String,
+        clients: usize,
+        ready: usize,
+        bots: usize,
+        round: usize,
+    }
+    // Only lock one room at a time
+    let rooms = rooms.lock().unwrap().all();
+    let stats: Vec<RoomStats> = rooms
+        .into_iter()
+        .map(|(id, state)| {
+            let state = state.lock().unwrap();
+            RoomStats {
+                labels: format!("{{room=\"{id}\"}}"),
+                clients: state.clients.len(),
+                ready: state.clients.values().filter(|client| client.ready).count(),
+                bots: state.numbers.bots,
+                round: state.round.num,
+            }
+        })
+        .collect();
+    let per_room = |f: fn(&RoomStats) -> usize| {
+        stats
+            .iter()
+            .map(move |room| (room.labels.clone(), f(room) as f64))
+            .collect::<Vec<_>>()
+    };
+    let total = |value: f64| [(String::new(), value)];
+    let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed) as f64;
+
+    let mut out = String::new();
+    metric(
+        &mut out,
+        "coots_clients",
+        "gauge",
+        "Connected clients",
+        per_room(|room| room.clients),
+    );
+    metric(
+        &mut out,
+        "coots_ready_players",
+        "gauge",
+        "Clients ready to play",
+        per_room(|room| room.ready),
+    );
+    metric(
+        &mut out,
+        "coots_bots_in_game",
+        "gauge",
+        "Bots still racing in the current session",
+        per_room(|room| room.bots),
+    );
+    metric(
+        &mut out,
+        "coots_round",
+        "gauge",
+        "Current round number",
+        per_room(|room| room.round),
+    );
+    metric(
+        &mut out,
+        "coots_ticks_total",
+        "counter",
+        "Ticks of the background thread",
+        total(load(&metrics.ticks)),
+    );
+    metric(
+        &mut out,
+        "coots_tick_overruns_total",
+        "counter",
+        "Ticks that took longer than the tick interval",
+        total(load(&metrics.tick_overruns)),
+    );
+    metric(
+        &mut out,
+        "coots_tick_duration_seconds",
+        "gauge",
+        "Duration of the last tick",
+        total(load(&metrics.last_tick_duration_micros) / 1e6),
+    );
+    metric(
+        &mut out,
+        "coots_messages_sent_total",
+        "counter",
+        "Messages sent to clients",
+        total(load(&metrics.messages_sent)),
+    );
+    metric(
+        &mut out,
+        "coots_bytes_sent_total",
+        "counter",
+        "Bytes sent to clients",
+        total(load(&metrics.bytes_sent)),
+    );
+    let rates = metrics.rates.lock().unwrap();
+    metric(
+        &mut out,
+        "coots_messages_sent_per_second",
+        "gauge",
+        "Messages sent to clients per second",
+        total(rates.messages_per_second),
+    );
+    metric(
+        &mut out,
+        "coots_bytes_sent_per_second",
+        "gauge",
+        "Bytes sent to clients per second",
+        total(rates.bytes_per_second),
+    );
+    mem::drop(rates);
+    let bots_data_size =
+        std::fs::metadata(run_dir().join("bots.data")).map_or(0, |meta| meta.len());
+    metric(
+        &mut out,
+        "coots_bots_data_bytes",
+        "gauge",
+        "Size of bots.data",
+        total(bots_data_size as f64),
+    );
+    out
+}
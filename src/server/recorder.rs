@@ -0,0 +1,54 @@
+use super::*;
+
+use match_recording::Event;
+
+/// Records what a spectator of the room sees for watching later with `--replay`,
+/// the file is written on its own thread and finished when this is dropped
+pub struct Recorder {
+    time: Timer,
+    snapshots: snapshot::Encoder,
+    events: std::sync::mpsc::Sender<Event>,
+}
+
+impl Recorder {
+    pub fn start(path: std::path::PathBuf) -> Self {
+        let (sender, events) = std::sync::mpsc::channel();
+        std::thread::spawn(move || match write(&path, events) {
+            Ok(()) => info!("Saved match recording {path:?}"),
+            Err(e) => error!("Failed to write match recording {path:?}: {e:?}"),
+        });
+        Self {
+            time: Timer::new(),
+            snapshots: default(),
+            events: sender,
+        }
+    }
+
+    pub fn record(&mut self, message: ServerMessage) {
+        let time = self.time.elapsed().as_secs_f64() as f32;
+        // Only fails if writing failed, which is logged already
+        let _ = self.events.send(Event { time, message });
+    }
+
+    pub fn record_frame(&mut self, frame: snapshot::Frame) {
+        let time = self.time.elapsed().as_secs_f64() as f32;
+        let snapshot = self.snapshots.encode(time, frame);
+        // The file gets every snapshot so each one can be a delta against the previous
+        self.snapshots.ack(snapshot.seq);
+        self.record(ServerMessage::Snapshot(snapshot));
+    }
+}
+
+fn write(path: &std::path::Path, events: std::sync::mpsc::Receiver<Event>) -> anyhow::Result<()> {
+    use std::io::Write;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    file.write_all(&match_recording::header())?;
+    for event in events {
+        bincode::serialize_into(&mut file, &event)?;
+    }
+    file.flush()?;
+    Ok(())
+}
@@ -0,0 +1,107 @@
+use super::*;
+
+#[derive(Serialize, Deserialize)]
+struct RecordRun {
+    track: Track,
+    record: Record,
+    replay: bots::MoveData,
+}
+
+/// Best time on every track along with the run itself for the ghost
+pub struct Records {
+    runs: HashMap<Track, RecordRun>,
+    save: std::sync::mpsc::Sender<()>,
+}
+
+impl Records {
+    /// Tracks without a record get the best run among bot recordings
+    pub fn load(
+        path: &std::path::Path,
+        bots: &bots::Data,
+        save: std::sync::mpsc::Sender<()>,
+    ) -> Self {
+        let runs: Vec<RecordRun> = load_json(path);
+        let mut runs: HashMap<Track, RecordRun> =
+            runs.into_iter().map(|run| (run.track, run)).collect();
+        for (&track, replays) in &bots.0 {
+            let best = replays
+                .iter()
+                .filter(|replay| !replay.data.is_empty())
+                .min_by_key(|replay| r32(replay.result().time));
+            if let Some(best) = best {
+                runs.entry(track).or_insert_with(|| RecordRun {
+                    track,
+                    record: Record {
+                        time: best.result().time,
+                        name: String::new(),
+                        date: 0,
+                    },
+                    replay: best.clone(),
+                });
+            }
+        }
+        Self { runs, save }
+    }
+
+    pub fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        let runs: Vec<&RecordRun> = self.runs.values().collect();
+        Ok(serde_json::to_vec(&runs)?)
+    }
+
+    pub fn get(&self, track: Track) -> Option<TrackRecord> {
+        let run = self.runs.get(&track)?;
+        Some(TrackRecord {
+            record: run.record.clone(),
+            ghost: run
+                .replay
+                .data
+                .iter()
+                .map(|data| GhostFrame {
+                    time: data.time,
+                    pos: data.data.pos,
+                    rot: data.data.rot,
+                })
+                .collect(),
+        })
+    }
+
+    /// Returns the new record if the run beats the current one
+    pub fn submit(&mut self, track: Track, name: &str, replay: &bots::MoveData) -> Option<Record> {
+        if replay.data.is_empty() {
+            return None;
+        }
+        let time = replay.result().time;
+        if let Some(run) = self.runs.get(&track) {
+            if run.record.time <= time {
+                return None;
+            }
+        }
+        let record = Record {
+            time,
+            name: name.to_owned(),
+            date: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs()),
+        };
+        self.runs.insert(
+            track,
+            RecordRun {
+                track,
+                record: record.clone(),
+                replay: replay.clone(),
+            },
+        );
+        let _ = self.save.send(());
+        Some(record)
+    }
+
+    pub fn table(&self) -> Vec<(Track, Record)> {
+        let mut table: Vec<(Track, Record)> = self
+            .runs
+            .values()
+            .map(|run| (run.track, run.record.clone()))
+            .collect();
+        table.sort_by_key(|(track, _)| (track.from, track.to));
+        table
+    }
+}
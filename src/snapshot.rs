@@ -0,0 +1,256 @@
+use super::*;
+
+/// Full state of all players as seen by one client
+pub type Frame = HashMap<Id, (PackedPlayer, Appearance)>;
+
+pub struct Quantizer {
+    bounds: Aabb2<f32>,
+    max_vel: f32,
+}
+
+fn quantize(value: f32, min: f32, max: f32) -> u16 {
+    (((value - min) / (max - min)).clamp(0.0, 1.0) * u16::MAX as f32).round() as u16
+}
+
+fn dequantize(value: u16, min: f32, max: f32) -> f32 {
+    min + (max - min) * value as f32 / u16::MAX as f32
+}
+
+fn quantize_signed(value: f32, max: f32) -> i16 {
+    ((value / max).clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16
+}
+
+fn dequantize_signed(value: i16, max: f32) -> f32 {
+    value as f32 / i16::MAX as f32 * max
+}
+
+impl Quantizer {
+    /// Positions are relative to the bounds of the local level,
+    /// so the server and the client need the same level (see [Level::checksum])
+    pub fn new(level: &Level, config: &Config) -> Self {
+        Self {
+            bounds: Aabb2::points_bounding_box(level.segments.iter().copied().flatten())
+                .extend_uniform(config.player_radius * 2.0),
+            max_vel: config.max_speed * config.movement_tolerance,
+        }
+    }
+
+    pub fn pack(&self, player: &Player) -> (PackedPlayer, Appearance) {
+        let Aabb2 { min, max } = self.bounds;
+        let state = PackedPlayer {
+            pos: [
+                quantize(player.pos.x, min.x, max.x),
+                quantize(player.pos.y, min.y, max.y),
+            ],
+            vel: [
+                quantize_signed(player.vel.x, self.max_vel),
+                quantize_signed(player.vel.y, self.max_vel),
+            ],
+            rot: ((player.rot.rem_euclid(2.0 * f32::PI) / (2.0 * f32::PI) * 65536.0) as u32 % 65536)
+                as u16,
+        };
+        let appearance = Appearance {
+            skin: player.skin.min(u16::MAX as usize) as u16,
+            color: (player.color.rem_euclid(1.0) * u8::MAX as f32).round() as u8,
+        };
+        (state, appearance)
+    }
+
+    pub fn unpack(&self, &(state, appearance): &(PackedPlayer, Appearance)) -> Player {
+        let Aabb2 { min, max } = self.bounds;
+        Player {
+            color: appearance.color as f32 / u8::MAX as f32,
+            skin: appearance.skin as usize,
+            pos: vec2(
+                dequantize(state.pos[0], min.x, max.x),
+                dequantize(state.pos[1], min.y, max.y),
+            ),
+            vel: vec2(
+                dequantize_signed(state.vel[0], self.max_vel),
+                dequantize_signed(state.vel[1], self.max_vel),
+            ),
+            rot: state.rot as f32 / 65536.0 * 2.0 * f32::PI,
+        }
+    }
+}
+
+const MAX_HISTORY: usize = 64;
+
+/// Server side, encodes snapshots as deltas against the last one acknowledged by the client
+#[derive(Default)]
+pub struct Encoder {
+    next_seq: u64,
+    acked: Option<u64>,
+    history: VecDeque<(u64, Frame)>,
+}
+
+impl Encoder {
+    pub fn ack(&mut self, seq: u64) {
+        if seq >= self.next_seq || self.acked.map_or(false, |acked| acked >= seq) {
+            return;
+        }
+        self.acked = Some(seq);
+        self.history.retain(|&(history_seq, _)| history_seq >= seq);
+    }
+
+    pub fn encode(&mut self, time: f32, frame: Frame) -> Snapshot {
+        let empty = Frame::new();
+        let (baseline, base) = match self
+            .acked
+            .and_then(|acked| self.history.iter().find(|&&(seq, _)| seq == acked))
+        {
+            Some((seq, base)) => (Some(*seq), base),
+            None => (None, &empty),
+        };
+        let updated = frame
+            .iter()
+            .filter_map(|(&id, &(state, appearance))| match base.get(&id) {
+                Some(&(old_state, old_appearance))
+                    if old_state == state && old_appearance == appearance =>
+                {
+                    None
+                }
+                Some(&(_, old_appearance)) => Some(PlayerDelta {
+                    id,
+                    state,
+                    appearance: (old_appearance != appearance).then_some(appearance),
+                }),
+                None => Some(PlayerDelta {
+                    id,
+                    state,
+                    appearance: Some(appearance),
+                }),
+            })
+            .collect();
+        let removed = base
+            .keys()
+            .filter(|id| !frame.contains_key(id))
+            .copied()
+            .collect();
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.history.push_back((seq, frame));
+        while self.history.len() > MAX_HISTORY {
+            self.history.pop_front();
+        }
+        Snapshot {
+            seq,
+            baseline,
+            time,
+            updated,
+            removed,
+        }
+    }
+}
+
+/// Client side, reconstructs full frames from delta snapshots
+#[derive(Default)]
+pub struct Decoder {
+    history: VecDeque<(u64, Frame)>,
+}
+
+impl Decoder {
+    /// Returns [None] if the baseline is not known, such snapshot should not be acknowledged
+    pub fn decode(&mut self, snapshot: Snapshot) -> Option<&Frame> {
+        let mut frame = match snapshot.baseline {
+            Some(baseline) => self
+                .history
+                .iter()
+                .find(|&&(seq, _)| seq == baseline)?
+                .1
+                .clone(),
+            None => Frame::new(),
+        };
+        for id in snapshot.removed {
+            frame.remove(&id);
+        }
+        for delta in snapshot.updated {
+            let appearance = match delta
+                .appearance
+                .or_else(|| frame.get(&delta.id).map(|&(_, appearance)| appearance))
+            {
+                Some(appearance) => appearance,
+                None => {
+                    warn!("Got player {} without appearance", delta.id);
+                    continue;
+                }
+            };
+            frame.insert(delta.id, (delta.state, appearance));
+        }
+        // Server never uses baselines older than the ones it already used
+        if let Some(baseline) = snapshot.baseline {
+            self.history.retain(|&(seq, _)| seq >= baseline);
+        }
+        self.history.push_back((snapshot.seq, frame));
+        while self.history.len() > MAX_HISTORY {
+            self.history.pop_front();
+        }
+        self.history.back().map(|(_, frame)| frame)
+    }
+}
+
+#[test]
+fn test_bandwidth() {
+    const PLAYERS: usize = 32;
+    const TICKS: usize = 100;
+    const TICKS_PER_SECOND: f32 = 10.0;
+    /// Half of the players moving at 10 ticks per second fit in about 3.5 KB/s
+    const MAX_BYTES_PER_SECOND: f32 = 4000.0;
+
+    let config = physics::test_config();
+    let level: Level = serde_json::from_str(include_str!("../level.json")).unwrap();
+    let quantizer = Quantizer::new(&level, &config);
+    let mut players: Vec<(Id, Player)> = (0..PLAYERS)
+        .map(|index| {
+            (
+                index as Id,
+                Player {
+                    color: index as f32 / PLAYERS as f32,
+                    skin: index,
+                    pos: level.cat_locations[index % level.cat_locations.len()],
+                    // Half of the players are standing still
+                    vel: if index % 2 == 0 {
+                        vec2(config.max_speed, 0.0).rotate(index as f32)
+                    } else {
+                        vec2::ZERO
+                    },
+                    rot: index as f32,
+                },
+            )
+        })
+        .collect();
+
+    let mut encoder = Encoder::default();
+    let mut decoder = Decoder::default();
+    let mut full_bytes = 0;
+    let mut compact_bytes = 0;
+    for tick in 0..TICKS {
+        let time = tick as f32 / TICKS_PER_SECOND;
+        for (_, player) in &mut players {
+            if player.vel != vec2::ZERO {
+                player.pos += player.vel / TICKS_PER_SECOND;
+                player.rot += 0.1;
+            }
+        }
+        full_bytes += bincode::serialize(&(time, &players)).unwrap().len();
+
+        let frame = players
+            .iter()
+            .map(|(id, player)| (*id, quantizer.pack(player)))
+            .collect();
+        let snapshot = encoder.encode(time, frame);
+        compact_bytes += bincode::serialize(&snapshot).unwrap().len();
+        let seq = snapshot.seq;
+        let decoded = decoder.decode(snapshot).unwrap();
+        for (id, player) in &players {
+            let decoded = quantizer.unpack(&decoded[id]);
+            assert!((decoded.pos - player.pos).len() < 0.01);
+            assert!((decoded.vel - player.vel).len() < 0.01);
+            assert_eq!(decoded.skin, player.skin);
+        }
+        encoder.ack(seq);
+    }
+    let seconds = TICKS as f32 / TICKS_PER_SECOND;
+    assert!(compact_bytes as f32 / seconds < MAX_BYTES_PER_SECOND);
+    assert!(compact_bytes * 2 < full_bytes);
+}
@@ -81,6 +81,62 @@ impl geng::ui::Widget for TextureButton<'_> {
     }
 }
 
+pub struct TextButton<'a> {
+    sense: &'a mut geng::ui::Sense,
+    clicked: bool,
+    text: String,
+    font: &'a geng::Font,
+    size: f32,
+}
+
+impl<'a> TextButton<'a> {
+    pub fn new(
+        cx: &'a geng::ui::Controller,
+        text: impl Into<String>,
+        font: &'a geng::Font,
+        sfx: &'a game::UiSfxAssets,
+        size: f32,
+    ) -> Self {
+        let sense: &'a mut geng::ui::Sense = cx.get_state();
+        let clicked = sense.take_clicked();
+        if clicked {
+            sfx.click.play();
+        }
+        let last_hover: &'a mut bool = cx.get_state();
+        if *last_hover != sense.is_hovered() {
+            *last_hover = sense.is_hovered();
+            sfx.hover.play();
+        }
+        Self {
+            sense,
+            clicked,
+            text: text.into(),
+            font,
+            size,
+        }
+    }
+    pub fn was_clicked(&self) -> bool {
+        self.clicked
+    }
+}
+
+impl geng::ui::Widget for TextButton<'_> {
+    fn sense(&mut self) -> Option<&mut geng::ui::Sense> {
+        Some(self.sense)
+    }
+    fn calc_constraints(&mut self, cx: &geng::ui::ConstraintsContext) -> geng::ui::Constraints {
+        calc_text_constraints(&self.text, self.font, self.size, cx)
+    }
+    fn draw(&mut self, cx: &mut geng::ui::DrawContext) {
+        let color = if self.sense.is_hovered() {
+            Rgba::new(1.0, 1.0, 0.5, 1.0)
+        } else {
+            Rgba::WHITE
+        };
+        draw_text(&self.text, self.font, self.size, color, cx);
+    }
+}
+
 pub struct TextureWidget<'a> {
     texture: &'a ugli::Texture,
     size: f64,